@@ -1,7 +1,24 @@
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
 
+    // Build scripts run on the host, so this checks the target instead of using `cfg`.
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("windows") {
+        return;
+    }
+
     windows::build! {
+        Windows::Foundation::TimeSpan,
+        Windows::Media::Control::{
+            GlobalSystemMediaTransportControlsSession,
+            GlobalSystemMediaTransportControlsSessionManager,
+            GlobalSystemMediaTransportControlsSessionTimelineProperties,
+        },
+        Windows::Win32::Foundation::CloseHandle,
+        Windows::Win32::System::Diagnostics::ToolHelp::{
+            CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+            TH32CS_SNAPPROCESS,
+        },
+        Windows::Win32::UI::KeyboardAndMouseInput::{keybd_event, KEYBD_EVENT_FLAGS},
         Windows::Win32::UI::WindowsAndMessaging::{
             GetForegroundWindow, GetWindowThreadProcessId,
         },
@@ -0,0 +1,62 @@
+//! Actions that gestures can be bound to in the config, which are run by the core.
+
+use crate::pattern::StreamPattern;
+use serde::{Deserialize, Serialize};
+use std::{io, process};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    None,
+    ToggleMuted,
+    ToggleSolo,
+    OpenMenu,
+    /// Moves the volume by the number of steps that the encoder was turned. Other gestures
+    /// count as a single step.
+    StepVolume,
+    /// Makes the first output device that matches the pattern the default one.
+    SetDefaultDevice(StreamPattern),
+    /// Presses a media key, which controls the media player that the system chooses.
+    Media(MediaKey),
+    /// Like `media`, but controls the media players of the channel's streams.
+    Player(MediaKey),
+    /// Seeks the media players of the channel's streams by this many seconds for each step.
+    Seek(i32),
+    /// Runs a command with the system's shell, without waiting for it to finish.
+    Shell(String),
+    SwitchProfile(String),
+    /// Like `step-volume`, but for every stream that matches the pattern, instead of the
+    /// channel's own streams.
+    StepStream(StreamPattern),
+    /// Toggles the mute state of every stream that matches the pattern, instead of the
+    /// channel's own streams.
+    ToggleStreamMuted(StreamPattern),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MediaKey {
+    PlayPause,
+    Stop,
+    Next,
+    Previous,
+}
+
+/// Starts the command with `cmd /C` on Windows, or `sh -c` elsewhere. The process is waited for
+/// on another thread, so it doesn't linger after it exits.
+pub fn spawn_shell(command: &str) -> io::Result<()> {
+    let mut child = if cfg!(windows) {
+        process::Command::new("cmd")
+            .arg("/C")
+            .arg(command)
+            .spawn()?
+    } else {
+        process::Command::new("sh").arg("-c").arg(command).spawn()?
+    };
+    std::thread::spawn(move || {
+        if let Err(e) = child.wait() {
+            log::warn!("could not wait for command: {}", e);
+        }
+    });
+    Ok(())
+}
@@ -1,9 +1,12 @@
+use crate::action::MediaKey;
 use std::{
     future::Future,
     pin::Pin,
     sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
 };
 
+use serde::{Deserialize, Serialize};
 use smol::channel::{Receiver, Sender};
 
 pub type VolumeLevel = f32;
@@ -64,28 +67,64 @@ pub enum AudioEvent {
         stream_id: StreamId,
         stream_event: StreamEvent,
     },
+    /// The focused window changed. The stream is the one of its application, if that plays
+    /// audio, and the application is described even if it doesn't.
     WindowFocusChanged {
         stream_id: Option<StreamId>,
+        application: Option<Application>,
     },
     DefaultDeviceChanged {
         stream_id: Option<StreamId>,
     },
+    DefaultInputDeviceChanged {
+        stream_id: Option<StreamId>,
+    },
 }
 
 #[derive(Debug)]
 pub enum StreamEvent {
     StateChanged(StreamState),
+    /// The highest level of the stream's audio since the last report, from 0 to 1. This is only
+    /// sent for streams that the core has subscribed to with [`AudioControl::MeterSubscribe`].
+    Peak(VolumeLevel),
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum AudioControl {
     StreamControl {
         stream_id: StreamId,
         stream_control: StreamControl,
     },
+    /// Starts sending [`StreamEvent::Peak`] events for the stream, about once per interval.
+    /// Backends should not measure streams that nobody is subscribed to.
+    MeterSubscribe {
+        stream_id: StreamId,
+        interval: Duration,
+    },
+    /// Stops sending [`StreamEvent::Peak`] events for the stream.
+    MeterUnsubscribe { stream_id: StreamId },
+    /// Makes the device the default output device. The backend confirms it with
+    /// [`AudioEvent::DefaultDeviceChanged`], or logs a warning if it can't change the default
+    /// device.
+    SetDefaultDevice { stream_id: StreamId },
+    /// Presses a media key.
+    Media(MediaKey),
+    /// Controls the media players that the streams belong to, instead of the one that the system
+    /// chooses. Each player is controlled once, even if several of the streams belong to it.
+    MediaControl {
+        stream_ids: Vec<StreamId>,
+        media_control: MediaControl,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaControl {
+    Key(MediaKey),
+    /// Moves the playback position by this many milliseconds, backwards if negative.
+    Seek(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum StreamControl {
     SetVolume(VolumeLevel),
     StepVolume(i32),
@@ -112,7 +151,7 @@ impl StreamId {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct StreamState {
     pub volume: f32,
     pub muted: bool,
@@ -127,11 +166,77 @@ impl Default for StreamState {
     }
 }
 
+/// A stable identity for a stream, which can be used to recognize the same device or
+/// application across sessions.
+///
+/// Unlike [`StreamId`], these may be saved to disk and compared between runs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum StreamIdentity {
+    Device {
+        /// The backend's identifier for the audio endpoint.
+        endpoint_id: String,
+        /// The role or form factor of the device (e.g. "speakers" or "headset"), if the backend
+        /// reports one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        role: Option<String>,
+    },
+    Session {
+        /// The endpoint ID of the device that the session belongs to.
+        endpoint_id: String,
+        /// The backend's identifier for the session, if it is stable across restarts.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        session_identifier: Option<String>,
+        /// Path to the executable of the process that owns the session.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        executable: Option<String>,
+    },
+}
+
+impl StreamIdentity {
+    /// Whether this stream is the one that the saved identity was taken from. Device roles are
+    /// only compared if the saved identity has one, because older versions of the backends
+    /// didn't report them.
+    pub fn matches(&self, saved: &StreamIdentity) -> bool {
+        match (self, saved) {
+            (
+                Self::Device { endpoint_id, role },
+                Self::Device {
+                    endpoint_id: saved_endpoint_id,
+                    role: saved_role,
+                },
+            ) => endpoint_id == saved_endpoint_id && (saved_role.is_none() || role == saved_role),
+            _ => self == saved,
+        }
+    }
+}
+
+/// The application of a window, as far as the backend can tell.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Application {
+    /// Path or file name of the executable of the window's process.
+    pub executable: Option<String>,
+    /// The name that the window system knows the application by, like a Wayland app ID.
+    pub name: Option<String>,
+}
+
+/// Whether a stream plays audio or records it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Speakers, headphones, and the applications that play through them.
+    #[default]
+    Output,
+    /// Microphones and other capture devices, and the applications that record from them.
+    Input,
+}
+
 #[derive(Debug)]
 pub struct StreamInfo {
     name: String,
     initial_state: StreamState,
     parent: Option<StreamId>,
+    identity: Option<StreamIdentity>,
+    direction: Direction,
 }
 
 impl StreamInfo {
@@ -139,6 +244,10 @@ impl StreamInfo {
         &self.name
     }
 
+    pub fn identity(&self) -> Option<&StreamIdentity> {
+        self.identity.as_ref()
+    }
+
     pub fn initial_state(&self) -> StreamState {
         self.initial_state
     }
@@ -146,12 +255,18 @@ impl StreamInfo {
     pub fn parent(&self) -> Option<StreamId> {
         self.parent
     }
+
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
 }
 
 pub struct StreamInfoBuilder {
     name: String,
     initial_state: StreamState,
     parent: Option<StreamId>,
+    identity: Option<StreamIdentity>,
+    direction: Direction,
 }
 
 impl StreamInfoBuilder {
@@ -160,6 +275,15 @@ impl StreamInfoBuilder {
             name,
             initial_state: Default::default(),
             parent: None,
+            identity: None,
+            direction: Direction::default(),
+        }
+    }
+
+    pub fn with_identity(self, identity: StreamIdentity) -> Self {
+        Self {
+            identity: Some(identity),
+            ..self
         }
     }
 
@@ -177,11 +301,17 @@ impl StreamInfoBuilder {
         }
     }
 
+    pub fn with_direction(self, direction: Direction) -> Self {
+        Self { direction, ..self }
+    }
+
     pub fn build(self) -> StreamInfo {
         StreamInfo {
             name: self.name,
             initial_state: self.initial_state,
             parent: self.parent,
+            identity: self.identity,
+            direction: self.direction,
         }
     }
 }
@@ -182,8 +182,13 @@ impl DeviceModel {
         }
     }
 
-    fn device_info(&self) -> DeviceInfo {
-        DeviceInfoBuilder::new(self.name().to_string(), self.num_channels()).build()
+    fn device_info(&self, serial_number: Option<&str>) -> DeviceInfo {
+        let builder = DeviceInfoBuilder::new(self.name().to_string(), self.num_channels());
+        match serial_number {
+            Some(serial_number) => builder.with_serial_number(serial_number.to_string()),
+            None => builder,
+        }
+        .build()
     }
 }
 
@@ -192,6 +197,7 @@ struct Device {
     state: DeviceState,
     ll_device: HidDevice,
     device_id: DeviceId,
+    serial_number: Option<String>,
 }
 
 impl Device {
@@ -209,6 +215,7 @@ impl Device {
             state,
             ll_device,
             device_id: DeviceId::new(),
+            serial_number: info.serial_number().map(str::to_string),
         }))
     }
 
@@ -217,7 +224,7 @@ impl Device {
     }
 
     fn info(&self) -> DeviceInfo {
-        self.model.device_info()
+        self.model.device_info(self.serial_number.as_deref())
     }
 
     fn channel_output(&mut self, index: usize, channel_output: ChannelOutput) {
@@ -234,6 +241,14 @@ impl Device {
                     ChannelOutput::MenuClosed => {
                         channel.menu_open = false;
                     }
+                    // The device has no display to show the menu on.
+                    ChannelOutput::MenuHighlighted(_) => {}
+                    ChannelOutput::Peak(peak) => {
+                        channel.peak = peak;
+                    }
+                    ChannelOutput::Soloed(soloed) => {
+                        channel.soloed = soloed;
+                    }
                 }
             }
         }
@@ -254,93 +269,47 @@ impl Device {
                     should_output = true;
                     assert_eq!(num_read, std::mem::size_of_val(&input));
 
-                    let now = Instant::now();
                     for index in 0..rev1::NUM_CHANNELS {
                         let channel = &mut state.channels[index];
 
                         let pressed = input.buttons & (1 << index) != 0;
                         let steps = input.encoders[index];
 
+                        // Gestures are recognized by the core.
                         if steps != 0 {
-                            if channel.menu_open {
-                                if steps > 0 {
-                                    for _ in 0..steps {
-                                        handle.blocking_send(ControlInput::ChannelInput(
-                                            self.device_id,
-                                            index,
-                                            ChannelInput::MenuNext,
-                                        ));
-                                    }
-                                } else {
-                                    for _ in steps..0 {
-                                        handle.blocking_send(ControlInput::ChannelInput(
-                                            self.device_id,
-                                            index,
-                                            ChannelInput::MenuPrevious,
-                                        ));
-                                    }
-                                }
-                            } else {
-                                handle.blocking_send(ControlInput::ChannelInput(
-                                    self.device_id,
-                                    index,
-                                    ChannelInput::StepVolume(steps.into()),
-                                ));
-                            }
+                            handle.blocking_send(ControlInput::ChannelInput(
+                                self.device_id,
+                                index,
+                                ChannelInput::Encoder(steps.into()),
+                            ));
                         }
-
-                        if pressed {
-                            if !channel.pressed {
-                                channel.long_press_timeout = Some(now + LONG_PRESS_DURATION);
-                            }
-                            if let Some(timeout) = channel.long_press_timeout {
-                                if now >= timeout {
-                                    let input = if channel.menu_open {
-                                        ChannelInput::CloseMenu
-                                    } else {
-                                        ChannelInput::OpenMenu
-                                    };
-                                    handle.blocking_send(ControlInput::ChannelInput(
-                                        self.device_id,
-                                        index,
-                                        input,
-                                    ));
-                                    channel.long_press_timeout = None;
-                                    channel.long_pressed = true;
-                                }
-                            }
-                        } else {
-                            if channel.pressed && !channel.long_pressed {
-                                let input = if channel.menu_open {
-                                    ChannelInput::MenuSelect
-                                } else {
-                                    ChannelInput::ToggleMuted
-                                };
-                                handle.blocking_send(ControlInput::ChannelInput(
-                                    self.device_id,
-                                    index,
-                                    input,
-                                ));
-                            }
-                            channel.pressed = false;
-                            channel.long_pressed = false;
-                            channel.long_press_timeout = None;
+                        if pressed != channel.pressed {
+                            handle.blocking_send(ControlInput::ChannelInput(
+                                self.device_id,
+                                index,
+                                ChannelInput::Button(pressed),
+                            ));
                         }
                         channel.pressed = pressed;
                     }
                 }
                 if should_output {
                     let mut output = rev1::Output::zeroed();
-                    let now = Instant::now();
-                    let blink_phase = now
-                        .saturating_duration_since(*MENU_BLINK_TIMER)
-                        .as_secs_f32()
-                        % MENU_BLINK_PERIOD.as_secs_f32()
-                        < MENU_BLINK_DURATION.as_secs_f32();
+                    let menu_phase = blink_phase(MENU_BLINK_PERIOD, MENU_BLINK_DURATION);
+                    let solo_phase = blink_phase(SOLO_BLINK_PERIOD, SOLO_BLINK_DURATION);
                     for index in 0..rev1::NUM_CHANNELS {
                         let channel = &mut state.channels[index];
                         // Handle output
-                        if channel.state.muted ^ (channel.menu_open && blink_phase) {
+                        let lit = if channel.menu_open {
+                            channel.state.muted ^ menu_phase
+                        } else if channel.soloed {
+                            // A fast blink, so it isn't mistaken for an open menu.
+                            solo_phase
+                        } else {
+                            // The LED also flashes while the audio clips, like on a mixer.
+                            channel.state.muted || channel.peak >= CLIP_LEVEL
+                        };
+                        if lit {
                             output.leds |= 1 << index;
                         }
                     }
@@ -366,17 +335,27 @@ impl DeviceState {
     }
 }
 
-const LONG_PRESS_DURATION: Duration = Duration::from_millis(500);
 const MENU_BLINK_PERIOD: Duration = Duration::from_millis(1000);
 const MENU_BLINK_DURATION: Duration = Duration::from_millis(250);
-static MENU_BLINK_TIMER: Lazy<Instant> = Lazy::new(|| Instant::now());
+const SOLO_BLINK_PERIOD: Duration = Duration::from_millis(250);
+const SOLO_BLINK_DURATION: Duration = Duration::from_millis(125);
+/// The peak level from which a channel's audio counts as clipping.
+const CLIP_LEVEL: f32 = 0.99;
+static BLINK_TIMER: Lazy<Instant> = Lazy::new(|| Instant::now());
+
+/// Whether a blinking LED is currently on, so that all blinking LEDs are in sync.
+fn blink_phase(period: Duration, duration: Duration) -> bool {
+    Instant::now()
+        .saturating_duration_since(*BLINK_TIMER)
+        .as_secs_f32()
+        % period.as_secs_f32()
+        < duration.as_secs_f32()
+}
 
 mod rev1 {
-    use std::time::Instant;
-
     use bytemuck::{Pod, Zeroable};
 
-    use crate::audio::StreamState;
+    use crate::audio::{StreamState, VolumeLevel};
 
     pub(crate) const NUM_CHANNELS: usize = 6;
 
@@ -401,10 +380,10 @@ mod rev1 {
     #[derive(Clone, Copy)]
     pub(crate) struct ChannelState {
         pub(crate) pressed: bool,
-        pub(crate) long_pressed: bool,
-        pub(crate) long_press_timeout: Option<Instant>,
         pub(crate) menu_open: bool,
+        pub(crate) soloed: bool,
         pub(crate) state: StreamState,
+        pub(crate) peak: VolumeLevel,
     }
 
     impl DeviceState {
@@ -412,10 +391,10 @@ mod rev1 {
             Self {
                 channels: [ChannelState {
                     pressed: false,
-                    long_pressed: false,
-                    long_press_timeout: None,
                     menu_open: false,
+                    soloed: false,
                     state: Default::default(),
+                    peak: 0.0,
                 }; NUM_CHANNELS],
             }
         }
@@ -1,2 +1,11 @@
 pub mod hidapi;
+#[cfg(target_os = "linux")]
+pub mod mpris;
+#[cfg(target_os = "linux")]
+pub mod pulse;
+#[cfg(target_os = "linux")]
+pub mod wayland;
+#[cfg(windows)]
 pub mod windows;
+#[cfg(target_os = "linux")]
+pub mod x11;
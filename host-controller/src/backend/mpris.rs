@@ -0,0 +1,252 @@
+//! Controls media players through MPRIS, the D-Bus interface that most media players on Linux
+//! implement. Players are matched to audio streams by the process that owns their bus name.
+
+use crate::{action::MediaKey, audio::MediaControl, process::ProcessTree};
+use std::collections::HashSet;
+use zbus::{fdo::DBusProxy, Connection, ConnectionBuilder};
+
+const PLAYER_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const PLAYER_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+pub struct Mpris {
+    connection: Connection,
+}
+
+impl Mpris {
+    /// Connects to the session bus.
+    pub async fn new() -> zbus::Result<Self> {
+        Ok(Self {
+            connection: Connection::session().await?,
+        })
+    }
+
+    /// Connects to the bus at a D-Bus address, like `unix:path=/run/user/1000/bus`.
+    pub async fn with_address(address: &str) -> zbus::Result<Self> {
+        Ok(Self {
+            connection: ConnectionBuilder::address(address)?.build().await?,
+        })
+    }
+
+    /// The bus names of the players that belong to one of the processes, or of all players if no
+    /// processes are given.
+    ///
+    /// Browsers and Electron apps own the bus name in their main process, but play audio from
+    /// another one, so players are matched through the process tree like the focused window is.
+    pub async fn players(&self, process_ids: Option<&HashSet<u32>>) -> zbus::Result<Vec<String>> {
+        let dbus = DBusProxy::new(&self.connection).await?;
+        let mut players = Vec::new();
+        let mut candidates: Vec<u32> = process_ids.into_iter().flatten().copied().collect();
+        candidates.sort_unstable();
+        // Reading the process tree is only needed if a player isn't in one of the processes.
+        let mut tree = None;
+        for name in dbus.list_names().await? {
+            if !name.as_str().starts_with(PLAYER_PREFIX) {
+                continue;
+            }
+            let process_ids = match process_ids {
+                Some(x) => x,
+                None => {
+                    players.push(name.to_string());
+                    continue;
+                }
+            };
+            // Players may go away while we're looking.
+            match dbus
+                .get_connection_unix_process_id(name.inner().clone())
+                .await
+            {
+                Ok(process_id) if process_ids.contains(&process_id) => {
+                    players.push(name.to_string());
+                }
+                Ok(process_id) => {
+                    let tree = tree.get_or_insert_with(|| {
+                        ProcessTree::snapshot().unwrap_or_else(|e| {
+                            log::warn!("could not read the running processes: {}", e);
+                            ProcessTree::new()
+                        })
+                    });
+                    if tree.resolve(process_id, &candidates).is_some() {
+                        players.push(name.to_string());
+                    }
+                }
+                Err(e) => log::debug!("could not get the process of {}: {}", name, e),
+            }
+        }
+        players.sort();
+        Ok(players)
+    }
+
+    pub async fn control(&self, player: &str, media_control: MediaControl) -> zbus::Result<()> {
+        match media_control {
+            MediaControl::Key(key) => {
+                let method = match key {
+                    MediaKey::PlayPause => "PlayPause",
+                    MediaKey::Stop => "Stop",
+                    MediaKey::Next => "Next",
+                    MediaKey::Previous => "Previous",
+                };
+                self.connection
+                    .call_method(
+                        Some(player),
+                        PLAYER_PATH,
+                        Some(PLAYER_INTERFACE),
+                        method,
+                        &(),
+                    )
+                    .await?;
+            }
+            MediaControl::Seek(millis) => {
+                // MPRIS offsets are in microseconds.
+                self.connection
+                    .call_method(
+                        Some(player),
+                        PLAYER_PATH,
+                        Some(PLAYER_INTERFACE),
+                        "Seek",
+                        &(millis * 1000),
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Controls every player that runs in one of the processes.
+    pub async fn control_processes(
+        &self,
+        process_ids: &HashSet<u32>,
+        media_control: MediaControl,
+    ) -> zbus::Result<()> {
+        let players = self.players(Some(process_ids)).await?;
+        if players.is_empty() {
+            log::debug!("no media player in processes {:?}", process_ids);
+        }
+        for player in players {
+            if let Err(e) = self.control(&player, media_control).await {
+                log::warn!("could not control {}: {}", player, e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        sync::{Arc, Mutex},
+    };
+
+    /// A private session bus, which is stopped when this is dropped.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Self {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(&["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("could not start dbus-daemon");
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Self {
+                daemon,
+                address: address.trim().to_string(),
+            }
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            self.daemon.kill().ok();
+            self.daemon.wait().ok();
+        }
+    }
+
+    /// Records the methods that are called on it.
+    #[derive(Clone, Default)]
+    struct StubPlayer {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[zbus::dbus_interface(name = "org.mpris.MediaPlayer2.Player")]
+    impl StubPlayer {
+        fn play_pause(&self) {
+            self.calls.lock().unwrap().push("PlayPause".into());
+        }
+
+        fn next(&self) {
+            self.calls.lock().unwrap().push("Next".into());
+        }
+
+        fn seek(&self, offset: i64) {
+            self.calls.lock().unwrap().push(format!("Seek {}", offset));
+        }
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn players_are_found_and_controlled() {
+        let bus = Bus::start();
+        smol::block_on(async {
+            let player = StubPlayer::default();
+            let _server = ConnectionBuilder::address(bus.address.as_str())
+                .unwrap()
+                .name("org.mpris.MediaPlayer2.stub")
+                .unwrap()
+                .serve_at(PLAYER_PATH, player.clone())
+                .unwrap()
+                .build()
+                .await
+                .unwrap();
+            let mpris = Mpris::with_address(&bus.address).await.unwrap();
+
+            // The stub player runs in this process.
+            let this_process: HashSet<u32> = [std::process::id()].iter().copied().collect();
+            let other_process: HashSet<u32> = [u32::MAX].iter().copied().collect();
+            let stub = vec!["org.mpris.MediaPlayer2.stub".to_string()];
+            assert_eq!(mpris.players(None).await.unwrap(), stub);
+            assert_eq!(mpris.players(Some(&this_process)).await.unwrap(), stub);
+            assert!(mpris
+                .players(Some(&other_process))
+                .await
+                .unwrap()
+                .is_empty());
+            // Players are also found from the processes that they started, like the audio
+            // process of a browser.
+            let mut child = std::process::Command::new("sleep")
+                .arg("10")
+                .spawn()
+                .unwrap();
+            let child_process: HashSet<u32> = [child.id()].iter().copied().collect();
+            assert_eq!(mpris.players(Some(&child_process)).await.unwrap(), stub);
+            child.kill().ok();
+            child.wait().ok();
+
+            mpris
+                .control(&stub[0], MediaControl::Key(MediaKey::PlayPause))
+                .await
+                .unwrap();
+            mpris
+                .control_processes(&this_process, MediaControl::Seek(-1500))
+                .await
+                .unwrap();
+            mpris
+                .control_processes(&other_process, MediaControl::Key(MediaKey::Next))
+                .await
+                .unwrap();
+            assert_eq!(
+                *player.calls.lock().unwrap(),
+                vec!["PlayPause".to_string(), "Seek -1500000".to_string()]
+            );
+        });
+    }
+}
@@ -0,0 +1,1225 @@
+//! Audio backend for PulseAudio, and for PipeWire through its PulseAudio server.
+//!
+//! Sinks and sources become device streams, and sink inputs and source outputs become session
+//! streams, with the device that they play to or record from as their parent. Monitor sources
+//! are left out, since they only mirror a sink.
+//!
+//! Peak meters are record streams with peak detection, which the server downsamples to one peak
+//! per interval. Sinks are measured through their monitor source, and sink inputs through the
+//! monitor of their sink, limited to the one sink input.
+
+use crate::action::MediaKey;
+use crate::audio::{
+    Application, AudioBackend, AudioControl, AudioEvent, AudioHandle, Direction, MediaControl,
+    StreamControl, StreamEvent, StreamId, StreamIdentity, StreamInfoBuilder, StreamState,
+    VolumeLevel,
+};
+use crate::backend::{mpris::Mpris, wayland, x11};
+use crate::process::ProcessTree;
+use bimap::BiHashMap;
+use libpulse_binding::{
+    callbacks::ListResult,
+    context::{
+        introspect::{ServerInfo, SinkInfo, SinkInputInfo, SourceInfo, SourceOutputInfo},
+        subscribe::{Facility, InterestMaskSet, Operation as SubscribeOperation},
+        Context, FlagSet, State,
+    },
+    def::BufferAttr,
+    error::{Code, PAErr},
+    mainloop::threaded::Mainloop,
+    proplist::{properties, Proplist},
+    sample::{Format, Spec},
+    stream::{FlagSet as StreamFlagSet, PeekResult, Stream},
+    volume::{ChannelVolumes, Volume},
+};
+use smol::{
+    channel::{Receiver, Sender},
+    future::FutureExt,
+};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    future::Future,
+    pin::Pin,
+    time::Duration,
+};
+
+type Index = u32;
+type ProcessId = u32;
+
+pub struct PulseAudioBackend(());
+
+impl PulseAudioBackend {
+    pub fn new() -> Self {
+        Self(())
+    }
+}
+
+impl AudioBackend for PulseAudioBackend {
+    type Error = PAErr;
+
+    fn start(self, handle: AudioHandle) -> Pin<Box<dyn Future<Output = Result<(), Self::Error>>>> {
+        Box::pin(async {
+            let mut runtime = Runtime::new(self, handle).await?;
+            runtime.run().await?;
+            Ok(())
+        })
+    }
+}
+
+struct Runtime {
+    handle: AudioHandle,
+    mainloop: RefCell<Mainloop>,
+    context: RefCell<Context>,
+    event_rx: Receiver<NotifyEvent>,
+    event_tx: Sender<NotifyEvent>,
+    object_ids: BiHashMap<StreamId, ObjectId>,
+    objects: HashMap<StreamId, ObjectInfo>,
+    /// The server's names of the default sink and source.
+    default_names: HashMap<Direction, String>,
+    mpris: Option<Mpris>,
+    focus: Option<Focus>,
+    window_focus: Option<StreamId>,
+    focused_application: Option<Application>,
+    /// The peak detecting record streams of the subscribed streams.
+    meters: HashMap<StreamId, Stream>,
+}
+
+impl Runtime {
+    async fn new(backend: PulseAudioBackend, handle: AudioHandle) -> Result<Self, PAErr> {
+        let _ = backend;
+
+        let (mainloop, context) = connect()?;
+
+        // Callbacks run on the mainloop's thread, so they can't wait for a full queue.
+        let (event_tx, event_rx) = smol::channel::unbounded();
+
+        let mpris = match Mpris::new().await {
+            Ok(x) => Some(x),
+            Err(e) => {
+                log::warn!("could not connect to the session bus: {}", e);
+                None
+            }
+        };
+
+        Ok(Self {
+            handle,
+            mainloop,
+            context,
+            event_rx,
+            event_tx,
+            object_ids: BiHashMap::new(),
+            objects: HashMap::new(),
+            default_names: HashMap::new(),
+            mpris,
+            focus: None,
+            window_focus: None,
+            focused_application: None,
+            meters: HashMap::new(),
+        })
+    }
+
+    async fn run(&mut self) -> Result<(), PAErr> {
+        // Init:
+
+        let event_tx = self.event_tx.clone();
+        self.locked(|context| {
+            context.set_subscribe_callback(Some(Box::new(move |facility, operation, index| {
+                let kind = match facility {
+                    Some(Facility::Sink) => Kind::Sink,
+                    Some(Facility::Source) => Kind::Source,
+                    Some(Facility::SinkInput) => Kind::SinkInput,
+                    Some(Facility::SourceOutput) => Kind::SourceOutput,
+                    Some(Facility::Server) => {
+                        notify(&event_tx, NotifyEvent::ServerChanged);
+                        return;
+                    }
+                    _ => return,
+                };
+                let object_id = ObjectId(kind, index);
+                match operation {
+                    Some(SubscribeOperation::Removed) => {
+                        notify(&event_tx, NotifyEvent::Removed(object_id));
+                    }
+                    Some(_) => notify(&event_tx, NotifyEvent::Changed(object_id)),
+                    None => {}
+                }
+            })));
+            context.subscribe(
+                InterestMaskSet::SINK
+                    | InterestMaskSet::SOURCE
+                    | InterestMaskSet::SINK_INPUT
+                    | InterestMaskSet::SOURCE_OUTPUT
+                    | InterestMaskSet::SERVER,
+                |success| {
+                    if !success {
+                        log::warn!("could not subscribe to server events");
+                    }
+                },
+            );
+        });
+
+        let event_tx = self.event_tx.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = watch_focus(&event_tx) {
+                log::warn!("stopped tracking the focused window: {}", e);
+            }
+        });
+
+        // The server answers in order, so devices are known before the sessions that belong to
+        // them, and both are known before the defaults.
+        self.request_all();
+        self.request_server_info();
+
+        loop {
+            let control_future = async { self.handle.recv().await.map(Incoming::Control) };
+            let notify_future = async {
+                Some(Incoming::Notify(
+                    self.event_rx.recv().await.expect("notifier hung up"),
+                ))
+            };
+            let incoming = control_future.or(notify_future).await;
+            log::debug!("incoming {:?}", incoming);
+            match incoming {
+                Some(Incoming::Control(control_message)) => match control_message {
+                    AudioControl::StreamControl {
+                        stream_id,
+                        stream_control,
+                    } => {
+                        if let Some(info) = self.objects.get(&stream_id) {
+                            match stream_control {
+                                StreamControl::SetVolume(volume) => {
+                                    self.set_volume(info.id, info.volume, volume);
+                                }
+                                StreamControl::SetMuted(muted) => {
+                                    self.set_muted(info.id, muted);
+                                }
+                                StreamControl::ToggleMuted => {
+                                    self.set_muted(info.id, !info.muted);
+                                }
+                                StreamControl::StepVolume(steps) => {
+                                    let volume =
+                                        (info.state().volume + steps as f32 * 0.02).clamp(0.0, 1.0);
+                                    self.set_volume(info.id, info.volume, volume);
+                                }
+                            }
+                        } else {
+                            log::warn!("received control for unknown stream {:?}", stream_id);
+                        }
+                    }
+                    AudioControl::MeterSubscribe {
+                        stream_id,
+                        interval,
+                    } => {
+                        if let Err(e) = self.subscribe_meter(stream_id, interval) {
+                            log::warn!("could not meter {:?}: {}", stream_id, e);
+                        }
+                    }
+                    AudioControl::MeterUnsubscribe { stream_id } => {
+                        self.unsubscribe_meter(stream_id);
+                    }
+                    AudioControl::SetDefaultDevice { stream_id } => {
+                        match self.objects.get(&stream_id) {
+                            Some(info) if info.parent.is_none() => {
+                                self.set_default(info.id.0, &info.pulse_name);
+                            }
+                            _ => log::warn!("{:?} is not a device", stream_id),
+                        }
+                    }
+                    AudioControl::Media(key) => {
+                        self.press_media_key(key).await;
+                    }
+                    AudioControl::MediaControl {
+                        stream_ids,
+                        media_control,
+                    } => {
+                        let process_ids: HashSet<ProcessId> = stream_ids
+                            .iter()
+                            .filter_map(|stream_id| self.objects.get(stream_id))
+                            .filter_map(|info| info.process_id)
+                            .collect();
+                        if let Some(mpris) = &self.mpris {
+                            if let Err(e) =
+                                mpris.control_processes(&process_ids, media_control).await
+                            {
+                                log::warn!("could not control media players: {}", e);
+                            }
+                        }
+                    }
+                },
+                Some(Incoming::Notify(notify_message)) => match notify_message {
+                    NotifyEvent::Changed(object_id) => {
+                        self.request_info(object_id);
+                    }
+                    NotifyEvent::Info(info) => {
+                        self.update(info).await;
+                    }
+                    NotifyEvent::Removed(object_id) => {
+                        if let Some(&stream_id) = self.object_ids.get_by_right(&object_id) {
+                            self.close(stream_id).await;
+                        }
+                    }
+                    NotifyEvent::FocusChanged(focus) => {
+                        self.focus = focus;
+                        self.update_focus().await;
+                    }
+                    NotifyEvent::ServerChanged => {
+                        self.request_server_info();
+                    }
+                    NotifyEvent::MeterReadable(stream_id) => {
+                        self.read_meter(stream_id).await;
+                    }
+                    NotifyEvent::Defaults { sink, source } => {
+                        for &(direction, ref name) in
+                            &[(Direction::Output, sink), (Direction::Input, source)]
+                        {
+                            let name = match name {
+                                Some(x) => x,
+                                None => continue,
+                            };
+                            if self.default_names.get(&direction) != Some(name) {
+                                self.default_names.insert(direction, name.clone());
+                                self.send_default(direction).await;
+                            }
+                        }
+                    }
+                },
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs `f` with the mainloop locked, which is needed for everything that touches the
+    /// context from outside of its own callbacks.
+    fn locked<T>(&self, f: impl FnOnce(&mut Context) -> T) -> T {
+        self.mainloop.borrow_mut().lock();
+        let result = f(&mut self.context.borrow_mut());
+        self.mainloop.borrow_mut().unlock();
+        result
+    }
+
+    fn request_all(&self) {
+        let event_tx = self.event_tx.clone();
+        self.locked(|context| {
+            let introspect = context.introspect();
+            let tx = event_tx.clone();
+            introspect.get_sink_info_list(move |result| {
+                if let ListResult::Item(info) = result {
+                    notify(&tx, NotifyEvent::Info(ObjectInfo::sink(info)));
+                }
+            });
+            let tx = event_tx.clone();
+            introspect.get_source_info_list(move |result| {
+                if let ListResult::Item(info) = result {
+                    if let Some(info) = ObjectInfo::source(info) {
+                        notify(&tx, NotifyEvent::Info(info));
+                    }
+                }
+            });
+            let tx = event_tx.clone();
+            introspect.get_sink_input_info_list(move |result| {
+                if let ListResult::Item(info) = result {
+                    notify(&tx, NotifyEvent::Info(ObjectInfo::sink_input(info)));
+                }
+            });
+            let tx = event_tx;
+            introspect.get_source_output_info_list(move |result| {
+                if let ListResult::Item(info) = result {
+                    notify(&tx, NotifyEvent::Info(ObjectInfo::source_output(info)));
+                }
+            });
+        });
+    }
+
+    fn request_info(&self, ObjectId(kind, index): ObjectId) {
+        let event_tx = self.event_tx.clone();
+        self.locked(|context| {
+            let introspect = context.introspect();
+            // Objects that were removed in the meantime answer with an error, which is ignored,
+            // because the removal is reported separately.
+            match kind {
+                Kind::Sink => {
+                    introspect.get_sink_info_by_index(index, move |result| {
+                        if let ListResult::Item(info) = result {
+                            notify(&event_tx, NotifyEvent::Info(ObjectInfo::sink(info)));
+                        }
+                    });
+                }
+                Kind::Source => {
+                    introspect.get_source_info_by_index(index, move |result| {
+                        if let ListResult::Item(info) = result {
+                            if let Some(info) = ObjectInfo::source(info) {
+                                notify(&event_tx, NotifyEvent::Info(info));
+                            }
+                        }
+                    });
+                }
+                Kind::SinkInput => {
+                    introspect.get_sink_input_info(index, move |result| {
+                        if let ListResult::Item(info) = result {
+                            notify(&event_tx, NotifyEvent::Info(ObjectInfo::sink_input(info)));
+                        }
+                    });
+                }
+                Kind::SourceOutput => {
+                    introspect.get_source_output_info(index, move |result| {
+                        if let ListResult::Item(info) = result {
+                            notify(
+                                &event_tx,
+                                NotifyEvent::Info(ObjectInfo::source_output(info)),
+                            );
+                        }
+                    });
+                }
+            }
+        });
+    }
+
+    fn request_server_info(&self) {
+        let event_tx = self.event_tx.clone();
+        self.locked(|context| {
+            context
+                .introspect()
+                .get_server_info(move |info: &ServerInfo| {
+                    notify(
+                        &event_tx,
+                        NotifyEvent::Defaults {
+                            sink: info.default_sink_name.as_deref().map(String::from),
+                            source: info.default_source_name.as_deref().map(String::from),
+                        },
+                    );
+                });
+        });
+    }
+
+    fn set_volume(
+        &self,
+        ObjectId(kind, index): ObjectId,
+        mut volume: ChannelVolumes,
+        level: VolumeLevel,
+    ) {
+        // Scaling keeps the balance between the channels.
+        volume.scale(Volume((level * Volume::NORMAL.0 as f32) as u32));
+        self.locked(|context| {
+            let mut introspect = context.introspect();
+            match kind {
+                Kind::Sink => {
+                    introspect.set_sink_volume_by_index(index, &volume, None);
+                }
+                Kind::Source => {
+                    introspect.set_source_volume_by_index(index, &volume, None);
+                }
+                Kind::SinkInput => {
+                    introspect.set_sink_input_volume(index, &volume, None);
+                }
+                Kind::SourceOutput => {
+                    introspect.set_source_output_volume(index, &volume, None);
+                }
+            }
+        });
+    }
+
+    fn set_muted(&self, ObjectId(kind, index): ObjectId, muted: bool) {
+        self.locked(|context| {
+            let mut introspect = context.introspect();
+            match kind {
+                Kind::Sink => {
+                    introspect.set_sink_mute_by_index(index, muted, None);
+                }
+                Kind::Source => {
+                    introspect.set_source_mute_by_index(index, muted, None);
+                }
+                Kind::SinkInput => {
+                    introspect.set_sink_input_mute(index, muted, None);
+                }
+                Kind::SourceOutput => {
+                    introspect.set_source_output_mute(index, muted, None);
+                }
+            }
+        });
+    }
+
+    /// Makes the sink or source the default one. The server confirms it with a server event.
+    fn set_default(&self, kind: Kind, name: &str) {
+        let callback = |success| {
+            if !success {
+                log::warn!("could not set the default device");
+            }
+        };
+        self.locked(|context| match kind {
+            Kind::Sink => {
+                context.set_default_sink(name, callback);
+            }
+            Kind::Source => {
+                context.set_default_source(name, callback);
+            }
+            Kind::SinkInput | Kind::SourceOutput => {}
+        });
+    }
+
+    /// Starts recording the stream's peaks, replacing its meter if it already has one.
+    fn subscribe_meter(&mut self, stream_id: StreamId, interval: Duration) -> Result<(), PAErr> {
+        self.unsubscribe_meter(stream_id);
+        let info = match self.objects.get(&stream_id) {
+            Some(x) => x,
+            None => {
+                log::warn!(
+                    "received meter subscription for unknown stream {:?}",
+                    stream_id
+                );
+                return Ok(());
+            }
+        };
+        let ObjectId(kind, index) = info.id;
+        let (source, monitored) = match kind {
+            Kind::Sink => (info.monitor_source.clone(), None),
+            Kind::Source => (Some(info.pulse_name.clone()), None),
+            Kind::SinkInput => {
+                let sink = info
+                    .parent
+                    .and_then(|parent| self.object_ids.get_by_right(&ObjectId(Kind::Sink, parent)))
+                    .and_then(|stream_id| self.objects.get(stream_id));
+                (
+                    sink.and_then(|sink| sink.monitor_source.clone()),
+                    Some(index),
+                )
+            }
+            // Recording applications would need a monitor of the recording, which the server
+            // doesn't offer. Their source is the closest thing.
+            Kind::SourceOutput => {
+                let source = info
+                    .parent
+                    .and_then(|parent| {
+                        self.object_ids
+                            .get_by_right(&ObjectId(Kind::Source, parent))
+                    })
+                    .and_then(|stream_id| self.objects.get(stream_id));
+                (source.map(|source| source.pulse_name.clone()), None)
+            }
+        };
+        let source = source.ok_or_else(|| PAErr::from(Code::NoEntity))?;
+
+        // Each sample is the peak of the audio since the previous one.
+        let spec = Spec {
+            format: Format::FLOAT32NE,
+            channels: 1,
+            rate: (1.0 / interval.as_secs_f32()).round().max(1.0) as u32,
+        };
+        let attr = BufferAttr {
+            maxlength: u32::MAX,
+            tlength: u32::MAX,
+            prebuf: u32::MAX,
+            minreq: u32::MAX,
+            fragsize: std::mem::size_of::<f32>() as u32,
+        };
+        let event_tx = self.event_tx.clone();
+        let stream = self.locked(|context| {
+            let mut stream = Stream::new(context, "Peak meter", &spec, None)
+                .ok_or_else(|| PAErr::from(Code::Internal))?;
+            // The callback runs on the mainloop's thread, and streams are only touched from the
+            // backend's, so it just says that there is something to read.
+            stream.set_read_callback(Some(Box::new(move |_| {
+                notify(&event_tx, NotifyEvent::MeterReadable(stream_id));
+            })));
+            if let Some(index) = monitored {
+                stream.set_monitor_stream(index)?;
+            }
+            stream.connect_record(
+                Some(&source),
+                Some(&attr),
+                StreamFlagSet::PEAK_DETECT
+                    | StreamFlagSet::ADJUST_LATENCY
+                    | StreamFlagSet::DONT_MOVE
+                    | StreamFlagSet::DONT_INHIBIT_AUTO_SUSPEND,
+            )?;
+            Ok(stream)
+        })?;
+        self.meters.insert(stream_id, stream);
+        Ok(())
+    }
+
+    /// Reads the peaks that a meter has recorded, and reports the highest one.
+    async fn read_meter(&mut self, stream_id: StreamId) {
+        let stream = match self.meters.get_mut(&stream_id) {
+            Some(x) => x,
+            // The meter may have been unsubscribed since.
+            None => return,
+        };
+        self.mainloop.borrow_mut().lock();
+        let mut peak: Option<f32> = None;
+        loop {
+            match stream.peek() {
+                Ok(PeekResult::Data(data)) => {
+                    for bytes in data.chunks_exact(4) {
+                        let sample = f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                        peak = Some(peak.unwrap_or(0.0).max(sample.abs()));
+                    }
+                }
+                Ok(PeekResult::Hole(_)) => {}
+                Ok(PeekResult::Empty) | Err(_) => break,
+            }
+            if stream.discard().is_err() {
+                break;
+            }
+        }
+        self.mainloop.borrow_mut().unlock();
+        // Earlier notifications may have read everything already.
+        if let Some(peak) = peak {
+            self.handle
+                .send(AudioEvent::StreamEvent {
+                    stream_id,
+                    stream_event: StreamEvent::Peak(peak.min(1.0)),
+                })
+                .await;
+        }
+    }
+
+    fn unsubscribe_meter(&mut self, stream_id: StreamId) {
+        if let Some(mut stream) = self.meters.remove(&stream_id) {
+            // The stream is dropped with the mainloop locked, too.
+            self.locked(move |_| {
+                stream.set_read_callback(None);
+                // The stream may have failed already, for example when its source was removed.
+                stream.disconnect().ok();
+            });
+        }
+    }
+
+    /// There are no system-wide media keys to press, so this controls the first player on the
+    /// session bus instead, like `playerctl` does.
+    async fn press_media_key(&self, key: MediaKey) {
+        let mpris = match &self.mpris {
+            Some(x) => x,
+            None => return,
+        };
+        let result = async {
+            if let Some(player) = mpris.players(None).await?.first() {
+                mpris.control(player, MediaControl::Key(key)).await?;
+            }
+            Ok::<(), zbus::Error>(())
+        };
+        if let Err(e) = result.await {
+            log::warn!("could not control media players: {}", e);
+        }
+    }
+
+    async fn update(&mut self, info: ObjectInfo) {
+        if let Some(&stream_id) = self.object_ids.get_by_right(&info.id) {
+            let old = &self.objects[&stream_id];
+            if old.parent == info.parent {
+                let state = info.state();
+                let changed = old.state() != state;
+                self.objects.insert(stream_id, info);
+                if changed {
+                    self.handle
+                        .send(AudioEvent::StreamEvent {
+                            stream_id,
+                            stream_event: StreamEvent::StateChanged(state),
+                        })
+                        .await;
+                }
+                return;
+            }
+            // The session was moved to another device. Streams can't change their parent, so it
+            // is opened again as a new stream.
+            self.close(stream_id).await;
+        }
+
+        let kind = info.id.0;
+        let parent = match info.parent {
+            Some(index) => {
+                match self
+                    .object_ids
+                    .get_by_right(&ObjectId(kind.device_kind(), index))
+                {
+                    Some(&parent) => Some(parent),
+                    None => {
+                        // For example, recording from a monitor source.
+                        log::debug!("ignoring {:?}, its device is unknown", info.id);
+                        return;
+                    }
+                }
+            }
+            None => None,
+        };
+        log::debug!("registering {:?}", info);
+        let stream_id = StreamId::new();
+        let mut stream_info = StreamInfoBuilder::new(info.description.clone())
+            .with_initial_state(info.state())
+            .with_identity(self.identity(&info, parent))
+            .with_direction(kind.direction());
+        if let Some(parent) = parent {
+            stream_info = stream_info.with_parent(parent);
+        }
+        self.handle
+            .send(AudioEvent::StreamOpened {
+                stream_id,
+                stream_info: stream_info.build(),
+            })
+            .await;
+        // The device may have become the default before it was known.
+        let is_default =
+            parent.is_none() && self.default_names.get(&kind.direction()) == Some(&info.pulse_name);
+        self.object_ids.insert(stream_id, info.id);
+        self.objects.insert(stream_id, info);
+        if is_default {
+            self.send_default(kind.direction()).await;
+        }
+        // The focused application may have just started playing.
+        self.update_focus().await;
+    }
+
+    async fn close(&mut self, stream_id: StreamId) {
+        self.unsubscribe_meter(stream_id);
+        if self.objects.remove(&stream_id).is_some() {
+            self.object_ids.remove_by_left(&stream_id);
+            self.handle
+                .send(AudioEvent::StreamClosed { stream_id })
+                .await;
+            self.update_focus().await;
+        }
+    }
+
+    /// Finds the application and stream of the focused window, and reports them if they
+    /// changed. Only playing applications follow the window focus.
+    async fn update_focus(&mut self) {
+        let (new_focus, application) = match &self.focus {
+            Some(Focus::Process(process_id)) => (
+                self.resolve_focus(*process_id),
+                Some(Application {
+                    executable: ProcessTree::executable(*process_id),
+                    name: None,
+                }),
+            ),
+            Some(Focus::Application(app_id)) => (
+                self.resolve_app_id(app_id),
+                Some(Application {
+                    executable: None,
+                    name: Some(app_id.clone()),
+                }),
+            ),
+            None => (None, None),
+        };
+        if self.window_focus != new_focus || self.focused_application != application {
+            self.window_focus = new_focus;
+            self.focused_application = application.clone();
+            self.handle
+                .send(AudioEvent::WindowFocusChanged {
+                    stream_id: self.window_focus,
+                    application,
+                })
+                .await;
+        }
+    }
+
+    fn resolve_focus(&self, focused: ProcessId) -> Option<StreamId> {
+        let playing = || {
+            self.objects
+                .iter()
+                .filter(|(_, info)| info.id.0 == Kind::SinkInput)
+        };
+        let mut candidates: Vec<ProcessId> =
+            playing().filter_map(|(_, info)| info.process_id).collect();
+        // Reading the process tree is only needed if the audio comes from another process.
+        let process_id = if candidates.contains(&focused) {
+            focused
+        } else {
+            candidates.sort_unstable();
+            candidates.dedup();
+            match ProcessTree::snapshot() {
+                Ok(tree) => tree.resolve(focused, &candidates)?,
+                Err(e) => {
+                    log::warn!("could not read the running processes: {}", e);
+                    return None;
+                }
+            }
+        };
+        playing()
+            .filter(|(_, info)| info.process_id == Some(process_id))
+            .map(|(&stream_id, _)| stream_id)
+            .min()
+    }
+
+    fn resolve_app_id(&self, app_id: &str) -> Option<StreamId> {
+        self.objects
+            .iter()
+            .filter(|(_, info)| {
+                info.id.0 == Kind::SinkInput
+                    && wayland::app_id_matches(
+                        app_id,
+                        info.executable.as_deref(),
+                        info.application_name.as_deref(),
+                    )
+            })
+            .map(|(&stream_id, _)| stream_id)
+            .min()
+    }
+
+    async fn send_default(&self, direction: Direction) {
+        let name = self.default_names.get(&direction);
+        let stream_id = self
+            .objects
+            .iter()
+            .find(|(_, info)| {
+                info.parent.is_none()
+                    && info.id.0.direction() == direction
+                    && Some(&info.pulse_name) == name
+            })
+            .map(|(&stream_id, _)| stream_id);
+        let event = match direction {
+            Direction::Output => AudioEvent::DefaultDeviceChanged { stream_id },
+            Direction::Input => AudioEvent::DefaultInputDeviceChanged { stream_id },
+        };
+        self.handle.send(event).await;
+    }
+
+    fn identity(&self, info: &ObjectInfo, parent: Option<StreamId>) -> StreamIdentity {
+        match parent {
+            None => StreamIdentity::Device {
+                endpoint_id: info.pulse_name.clone(),
+                role: info.role.clone(),
+            },
+            Some(parent) => StreamIdentity::Session {
+                endpoint_id: self.objects[&parent].pulse_name.clone(),
+                session_identifier: None,
+                executable: info.executable.clone(),
+            },
+        }
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        self.locked(|context| context.disconnect());
+        self.mainloop.borrow_mut().stop();
+    }
+}
+
+/// Reports the focused window, through the Wayland compositor if it supports that, and through
+/// X11 otherwise. This blocks, so it runs on its own thread.
+fn watch_focus(event_tx: &Sender<NotifyEvent>) -> Result<(), Box<dyn std::error::Error>> {
+    match wayland::FocusWatcher::new() {
+        Ok(watcher) => {
+            return watcher.run(|app_id| {
+                notify(
+                    event_tx,
+                    NotifyEvent::FocusChanged(app_id.map(Focus::Application)),
+                );
+            });
+        }
+        Err(e) => log::debug!("could not track the focused window on Wayland: {}", e),
+    }
+    let watcher = x11::FocusWatcher::new()?;
+    watcher.run(|process_id| {
+        notify(
+            event_tx,
+            NotifyEvent::FocusChanged(process_id.map(Focus::Process)),
+        );
+    })
+}
+
+/// Connects to the server, and waits until the connection is ready.
+fn connect() -> Result<(RefCell<Mainloop>, RefCell<Context>), PAErr> {
+    let mut mainloop = Mainloop::new().ok_or_else(|| PAErr::from(Code::Internal))?;
+    let mut context =
+        Context::new(&mainloop, "WindowMaster").ok_or_else(|| PAErr::from(Code::Internal))?;
+
+    // The callback runs on the mainloop's thread, so it only wakes up this one, which reads the
+    // state with the mainloop locked.
+    let (state_tx, state_rx) = std::sync::mpsc::channel();
+    context.set_state_callback(Some(Box::new(move || {
+        state_tx.send(()).ok();
+    })));
+    context.connect(None, FlagSet::NOFLAGS, None)?;
+    mainloop.start()?;
+    loop {
+        mainloop.lock();
+        let state = context.get_state();
+        let error = context.errno();
+        mainloop.unlock();
+        match state {
+            State::Ready => break,
+            State::Failed | State::Terminated => {
+                mainloop.stop();
+                return Err(error);
+            }
+            // The sender lives as long as the callback, so this only returns on a change.
+            _ => {
+                state_rx.recv().ok();
+            }
+        }
+    }
+    mainloop.lock();
+    context.set_state_callback(None);
+    mainloop.unlock();
+    Ok((RefCell::new(mainloop), RefCell::new(context)))
+}
+
+fn notify(event_tx: &Sender<NotifyEvent>, event: NotifyEvent) {
+    smol::block_on(event_tx.send(event)).ok();
+}
+
+/// Enumeration of all incoming message types, for parallel awaiting.
+#[derive(Debug)]
+enum Incoming {
+    Control(AudioControl),
+    Notify(NotifyEvent),
+}
+
+#[derive(Debug)]
+enum NotifyEvent {
+    Changed(ObjectId),
+    Removed(ObjectId),
+    Info(ObjectInfo),
+    FocusChanged(Option<Focus>),
+    ServerChanged,
+    MeterReadable(StreamId),
+    Defaults {
+        sink: Option<String>,
+        source: Option<String>,
+    },
+}
+
+/// The focused window, as far as the window system tells.
+#[derive(Debug)]
+enum Focus {
+    /// The process of an X11 window.
+    Process(ProcessId),
+    /// The app ID of a Wayland window.
+    Application(String),
+}
+
+/// The kinds of server objects that are streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Sink,
+    Source,
+    SinkInput,
+    SourceOutput,
+}
+
+impl Kind {
+    fn direction(self) -> Direction {
+        match self {
+            Self::Sink | Self::SinkInput => Direction::Output,
+            Self::Source | Self::SourceOutput => Direction::Input,
+        }
+    }
+
+    /// The kind of the devices that objects of this kind belong to.
+    fn device_kind(self) -> Self {
+        match self {
+            Self::Sink | Self::SinkInput => Self::Sink,
+            Self::Source | Self::SourceOutput => Self::Source,
+        }
+    }
+}
+
+/// Indices are only unique among objects of the same kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct ObjectId(Kind, Index);
+
+#[derive(Debug)]
+struct ObjectInfo {
+    id: ObjectId,
+    /// The server's name for the object, which identifies devices across restarts.
+    pulse_name: String,
+    description: String,
+    /// The index of the sink or source that a session belongs to.
+    parent: Option<Index>,
+    volume: ChannelVolumes,
+    muted: bool,
+    role: Option<String>,
+    /// The server's name of the monitor source of a sink.
+    monitor_source: Option<String>,
+    process_id: Option<ProcessId>,
+    executable: Option<String>,
+    application_name: Option<String>,
+}
+
+impl ObjectInfo {
+    fn sink(info: &SinkInfo) -> Self {
+        Self {
+            monitor_source: info.monitor_source_name.as_deref().map(String::from),
+            ..Self::device(
+                ObjectId(Kind::Sink, info.index),
+                &info.name,
+                &info.description,
+                info.volume,
+                info.mute,
+                &info.proplist,
+            )
+        }
+    }
+
+    fn source(info: &SourceInfo) -> Option<Self> {
+        if info.monitor_of_sink.is_some() {
+            return None;
+        }
+        Some(Self::device(
+            ObjectId(Kind::Source, info.index),
+            &info.name,
+            &info.description,
+            info.volume,
+            info.mute,
+            &info.proplist,
+        ))
+    }
+
+    fn sink_input(info: &SinkInputInfo) -> Self {
+        Self::session(
+            ObjectId(Kind::SinkInput, info.index),
+            &info.name,
+            info.sink,
+            info.volume,
+            info.mute,
+            &info.proplist,
+        )
+    }
+
+    fn source_output(info: &SourceOutputInfo) -> Self {
+        Self::session(
+            ObjectId(Kind::SourceOutput, info.index),
+            &info.name,
+            info.source,
+            info.volume,
+            info.mute,
+            &info.proplist,
+        )
+    }
+
+    fn device(
+        id: ObjectId,
+        name: &Option<Cow<str>>,
+        description: &Option<Cow<str>>,
+        volume: ChannelVolumes,
+        muted: bool,
+        proplist: &Proplist,
+    ) -> Self {
+        let pulse_name = name.as_deref().unwrap_or_default().to_string();
+        Self {
+            id,
+            description: description
+                .as_deref()
+                .map(String::from)
+                .unwrap_or_else(|| pulse_name.clone()),
+            pulse_name,
+            parent: None,
+            volume,
+            muted,
+            role: proplist.get_str(properties::DEVICE_FORM_FACTOR),
+            monitor_source: None,
+            process_id: None,
+            executable: None,
+            application_name: None,
+        }
+    }
+
+    fn session(
+        id: ObjectId,
+        name: &Option<Cow<str>>,
+        parent: Index,
+        volume: ChannelVolumes,
+        muted: bool,
+        proplist: &Proplist,
+    ) -> Self {
+        let pulse_name = name.as_deref().unwrap_or_default().to_string();
+        let application_name = proplist.get_str(properties::APPLICATION_NAME);
+        Self {
+            id,
+            // The stream name is often just "Playback", so the application's name is better.
+            description: application_name
+                .clone()
+                .unwrap_or_else(|| pulse_name.clone()),
+            pulse_name,
+            parent: Some(parent),
+            volume,
+            muted,
+            role: None,
+            monitor_source: None,
+            process_id: proplist
+                .get_str(properties::APPLICATION_PROCESS_ID)
+                .and_then(|pid| pid.parse().ok()),
+            executable: proplist.get_str(properties::APPLICATION_PROCESS_BINARY),
+            application_name,
+        }
+    }
+
+    fn state(&self) -> StreamState {
+        StreamState {
+            // Volumes above 100% are possible, but the core only goes up to 100%.
+            volume: (self.volume.max().0 as f32 / Volume::NORMAL.0 as f32).min(1.0),
+            muted: self.muted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smol::Timer;
+    use std::{
+        fs::File,
+        process::{Child, Command, Stdio},
+    };
+
+    const SINK_NAME: &str = "windowmaster_test";
+
+    fn pactl(args: &[&str]) -> String {
+        let output = Command::new("pactl")
+            .args(args)
+            .output()
+            .expect("could not run pactl");
+        assert!(output.status.success(), "pactl {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    /// A null sink, which is removed again when this is dropped, together with the default sink
+    /// that was set before.
+    struct NullSink {
+        module: String,
+        default_sink: String,
+    }
+
+    impl NullSink {
+        fn load() -> Self {
+            let default_sink = pactl(&["get-default-sink"]);
+            let module = pactl(&[
+                "load-module",
+                "module-null-sink",
+                &format!("sink_name={}", SINK_NAME),
+            ]);
+            Self {
+                module,
+                default_sink,
+            }
+        }
+    }
+
+    impl Drop for NullSink {
+        fn drop(&mut self) {
+            // This also runs when the test fails, so errors are ignored.
+            Command::new("pactl")
+                .args(&["unload-module", &self.module])
+                .status()
+                .ok();
+            Command::new("pactl")
+                .args(&["set-default-sink", &self.default_sink])
+                .status()
+                .ok();
+        }
+    }
+
+    /// Plays silence to the null sink until it is dropped.
+    struct Playback(Child);
+
+    impl Playback {
+        fn start() -> Self {
+            let child = Command::new("pacat")
+                .args(&["--playback", &format!("--device={}", SINK_NAME)])
+                .stdin(File::open("/dev/zero").unwrap())
+                .stdout(Stdio::null())
+                .spawn()
+                .expect("could not run pacat");
+            Self(child)
+        }
+    }
+
+    impl Drop for Playback {
+        fn drop(&mut self) {
+            self.0.kill().ok();
+            self.0.wait().ok();
+        }
+    }
+
+    /// Waits for the first event that `f` accepts, skipping the others.
+    async fn wait_for<T>(
+        event_rx: &Receiver<AudioEvent>,
+        mut f: impl FnMut(AudioEvent) -> Option<T>,
+    ) -> T {
+        let wait = async {
+            loop {
+                let event = event_rx.recv().await.expect("backend stopped");
+                if let Some(x) = f(event) {
+                    return x;
+                }
+            }
+        };
+        let timeout = async {
+            Timer::after(Duration::from_secs(5)).await;
+            panic!("timed out waiting for an event");
+        };
+        wait.or(timeout).await
+    }
+
+    #[test]
+    #[ignore = "needs a PulseAudio or PipeWire server, and pactl and pacat"]
+    fn streams_follow_the_server() {
+        let _sink = NullSink::load();
+        let (event_tx, event_rx) = smol::channel::unbounded();
+        let (_control_tx, control_rx) = smol::channel::unbounded();
+        let backend = PulseAudioBackend::new().start(AudioHandle::new(event_tx, control_rx));
+
+        let test = async {
+            let sink_id = wait_for(&event_rx, |event| match event {
+                AudioEvent::StreamOpened {
+                    stream_id,
+                    stream_info,
+                } => match stream_info.identity() {
+                    Some(StreamIdentity::Device { endpoint_id, .. })
+                        if endpoint_id == SINK_NAME =>
+                    {
+                        assert_eq!(stream_info.parent(), None);
+                        assert_eq!(stream_info.direction(), Direction::Output);
+                        Some(stream_id)
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .await;
+
+            let _playback = Playback::start();
+            wait_for(&event_rx, |event| match event {
+                AudioEvent::StreamOpened { stream_info, .. }
+                    if stream_info.parent() == Some(sink_id) =>
+                {
+                    Some(())
+                }
+                _ => None,
+            })
+            .await;
+
+            pactl(&["set-sink-volume", SINK_NAME, "50%"]);
+            let state = wait_for(&event_rx, |event| match event {
+                AudioEvent::StreamEvent {
+                    stream_id,
+                    stream_event: StreamEvent::StateChanged(state),
+                } if stream_id == sink_id => Some(state),
+                _ => None,
+            })
+            .await;
+            assert!((state.volume - 0.5).abs() < 0.01, "{:?}", state);
+
+            pactl(&["set-default-sink", SINK_NAME]);
+            wait_for(&event_rx, |event| match event {
+                AudioEvent::DefaultDeviceChanged {
+                    stream_id: Some(stream_id),
+                } if stream_id == sink_id => Some(()),
+                _ => None,
+            })
+            .await;
+        };
+
+        smol::block_on(async {
+            test.or(async {
+                backend.await.expect("backend failed");
+                panic!("backend stopped");
+            })
+            .await
+        });
+    }
+}
@@ -0,0 +1,3 @@
+mod audio;
+
+pub use self::audio::PulseAudioBackend;
@@ -0,0 +1,165 @@
+//! Tracks the focused window on Wayland, through the `wlr-foreign-toplevel-management` protocol
+//! that wlroots-based compositors and KWin offer to task bars.
+//!
+//! The protocol names the application of each window by its app ID, but not its process. The app
+//! ID is usually the name of the program or its desktop file, so it is matched to audio streams by
+//! their executables and application names instead.
+
+use std::{cell::RefCell, collections::HashMap, error::Error, rc::Rc};
+use wayland_client::{Display, EventQueue, GlobalManager};
+use wayland_protocols::wlr::unstable::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1::{self, ZwlrForeignToplevelHandleV1},
+    zwlr_foreign_toplevel_manager_v1::{self, ZwlrForeignToplevelManagerV1},
+};
+
+/// The windows by their protocol object IDs.
+type Toplevels = Rc<RefCell<HashMap<u32, Toplevel>>>;
+
+#[derive(Debug, Default)]
+struct Toplevel {
+    app_id: Option<String>,
+    activated: bool,
+}
+
+pub struct FocusWatcher {
+    // The connection is closed when this is dropped.
+    _display: Display,
+    event_queue: EventQueue,
+    toplevels: Toplevels,
+}
+
+impl FocusWatcher {
+    /// Connects to the compositor in `$WAYLAND_DISPLAY`. This fails if the compositor doesn't
+    /// support the protocol.
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let display = Display::connect_to_env()?;
+        let mut event_queue = display.create_event_queue();
+        let attached = (*display).clone().attach(event_queue.token());
+        let globals = GlobalManager::new(&attached);
+        event_queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+
+        let toplevels = Toplevels::default();
+        let manager = globals.instantiate_range::<ZwlrForeignToplevelManagerV1>(1, 3)?;
+        let manager_toplevels = Rc::clone(&toplevels);
+        manager.quick_assign(move |_, event, _| {
+            if let zwlr_foreign_toplevel_manager_v1::Event::Toplevel { toplevel } = event {
+                manager_toplevels
+                    .borrow_mut()
+                    .insert(toplevel.as_ref().id(), Toplevel::default());
+                let toplevels = Rc::clone(&manager_toplevels);
+                toplevel.quick_assign(move |handle, event, _| {
+                    handle_event(&toplevels, &handle, event);
+                });
+            }
+        });
+        // The compositor announces the windows that are already open, and their state.
+        event_queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+
+        Ok(Self {
+            _display: display,
+            event_queue,
+            toplevels,
+        })
+    }
+
+    /// The app ID of the focused window, if there is one and it has an app ID.
+    pub fn focused_app_id(&self) -> Option<String> {
+        self.toplevels
+            .borrow()
+            .values()
+            .find(|toplevel| toplevel.activated)
+            .and_then(|toplevel| toplevel.app_id.clone())
+    }
+
+    /// Calls `on_change` with the app ID of the focused window, and again every time it changes.
+    /// This blocks until the connection fails, so it should run on its own thread.
+    pub fn run(mut self, mut on_change: impl FnMut(Option<String>)) -> Result<(), Box<dyn Error>> {
+        let mut focus = self.focused_app_id();
+        on_change(focus.clone());
+        loop {
+            self.event_queue.dispatch(&mut (), |_, _, _| {})?;
+            let new_focus = self.focused_app_id();
+            if new_focus != focus {
+                focus = new_focus;
+                on_change(focus.clone());
+            }
+        }
+    }
+}
+
+fn handle_event(
+    toplevels: &Toplevels,
+    handle: &ZwlrForeignToplevelHandleV1,
+    event: zwlr_foreign_toplevel_handle_v1::Event,
+) {
+    let id = handle.as_ref().id();
+    let mut toplevels = toplevels.borrow_mut();
+    match event {
+        zwlr_foreign_toplevel_handle_v1::Event::AppId { app_id } => {
+            if let Some(toplevel) = toplevels.get_mut(&id) {
+                toplevel.app_id = Some(app_id);
+            }
+        }
+        zwlr_foreign_toplevel_handle_v1::Event::State { state } => {
+            if let Some(toplevel) = toplevels.get_mut(&id) {
+                // The states are an array of native-endian 32-bit values.
+                toplevel.activated = state.chunks_exact(4).any(|value| {
+                    u32::from_ne_bytes([value[0], value[1], value[2], value[3]])
+                        == zwlr_foreign_toplevel_handle_v1::State::Activated as u32
+                });
+            }
+        }
+        zwlr_foreign_toplevel_handle_v1::Event::Closed => {
+            toplevels.remove(&id);
+            handle.destroy();
+        }
+        _ => {}
+    }
+}
+
+/// Whether a window's app ID belongs to an application with this executable or name. App IDs are
+/// often reverse domain names, like `org.mozilla.firefox`, so the last part is enough.
+pub fn app_id_matches(
+    app_id: &str,
+    executable: Option<&str>,
+    application_name: Option<&str>,
+) -> bool {
+    let app_id = app_id.to_lowercase();
+    let short_app_id = app_id.rsplit('.').next().unwrap_or(&app_id);
+    let matches = |name: &str| {
+        let name = name.to_lowercase();
+        name == app_id || name == short_app_id
+    };
+    // The executable may be a full path.
+    executable
+        .and_then(|executable| executable.rsplit('/').next())
+        .is_some_and(matches)
+        || application_name.is_some_and(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn app_ids() {
+        assert!(app_id_matches("firefox", Some("firefox"), None));
+        assert!(app_id_matches(
+            "org.mozilla.firefox",
+            Some("/usr/lib/firefox/firefox"),
+            None
+        ));
+        assert!(app_id_matches("Spotify", None, Some("spotify")));
+        assert!(app_id_matches(
+            "org.gnome.Rhythmbox3",
+            Some("rhythmbox"),
+            Some("Rhythmbox3")
+        ));
+        assert!(!app_id_matches(
+            "org.gnome.Nautilus",
+            Some("firefox"),
+            Some("Firefox")
+        ));
+        assert!(!app_id_matches("firefox", None, None));
+    }
+}
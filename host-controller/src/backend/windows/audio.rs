@@ -1,15 +1,19 @@
+use super::{com, media};
+use crate::action::MediaKey;
 use crate::audio::{
-    AudioBackend, AudioControl, AudioEvent, AudioHandle, StreamControl, StreamEvent, StreamId,
-    StreamInfo, StreamInfoBuilder, StreamState,
+    Application, AudioBackend, AudioControl, AudioEvent, AudioHandle, Direction, StreamControl,
+    StreamEvent, StreamId, StreamIdentity, StreamInfo, StreamInfoBuilder, StreamState,
 };
-use crate::bindings::Windows::Win32::UI::WindowsAndMessaging::{
-    GetForegroundWindow, GetWindowThreadProcessId,
+use crate::bindings::Windows::Win32::UI::{
+    KeyboardAndMouseInput::{keybd_event, KEYBD_EVENT_FLAGS},
+    WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId},
 };
+use crate::process::ProcessTree;
 use bimap::BiHashMap;
 use smol::{
     channel::{Receiver, Sender},
     future::FutureExt,
-    Timer,
+    Task, Timer,
 };
 use std::{collections::HashMap, fmt::Debug, future::Future, pin::Pin, time::Duration};
 use win32_coreaudio::{
@@ -17,7 +21,7 @@ use win32_coreaudio::{
     AudioEndpointVolume, AudioEndpointVolumeCallback, AudioSessionControl, AudioSessionControl2,
     AudioSessionDisconnectReason, AudioSessionEvents, AudioSessionManager2,
     AudioSessionNotification, DataFlowMask, Device, DeviceEnumerator, DeviceState, DeviceStateMask,
-    NotificationClient, NotificationData, Property, PropertyStore, SimpleAudioVolume,
+    NotificationClient, NotificationData, Property, PropertyKey, PropertyStore, SimpleAudioVolume,
     StorageAccessMode, DEVICE_FRIENDLY_NAME,
 };
 use win32_coreaudio::{
@@ -59,6 +63,10 @@ struct Runtime {
     sessions: HashMap<StreamId, AudioSession>,
     process_ids: BiHashMap<StreamId, ProcessId>,
     window_focus: Option<StreamId>,
+    focused_process: Option<ProcessId>,
+    /// Whether sessions came or went since the focus was last resolved.
+    focus_stale: bool,
+    meters: HashMap<StreamId, Meter>,
 }
 
 impl Runtime {
@@ -84,6 +92,9 @@ impl Runtime {
             sessions: HashMap::new(),
             process_ids: BiHashMap::new(),
             window_focus: None,
+            focused_process: None,
+            focus_stale: false,
+            meters: HashMap::new(),
         })
     }
 
@@ -111,11 +122,16 @@ impl Runtime {
             .device_enumerator
             .register_endpoint_notification(notifier)?;
 
-        let device_list = self
-            .device_enumerator
-            .enum_audio_endpoints(DataFlowMask::Render, DeviceStateMask::ACTIVE)?;
-        for ll_device in &device_list {
-            self.add_device(ll_device).await?;
+        for &(data_flow, direction) in &[
+            (DataFlowMask::Render, Direction::Output),
+            (DataFlowMask::Capture, Direction::Input),
+        ] {
+            let device_list = self
+                .device_enumerator
+                .enum_audio_endpoints(data_flow, DeviceStateMask::ACTIVE)?;
+            for ll_device in &device_list {
+                self.add_device(ll_device, direction).await?;
+            }
         }
 
         let default_device: DeviceId = self
@@ -130,6 +146,21 @@ impl Runtime {
             })
             .await;
 
+        // Unlike outputs, there may not be any capture device at all.
+        let default_input_device = self
+            .device_enumerator
+            .get_default_audio_endpoint(DataFlow::Capture, DeviceRole::Console)
+            .and_then(|device| device.get_id())
+            .map(DeviceId)
+            .ok();
+        let default_stream_id = default_input_device
+            .and_then(|device_id| self.device_ids.get_by_right(&device_id).cloned());
+        self.handle
+            .send(AudioEvent::DefaultInputDeviceChanged {
+                stream_id: default_stream_id,
+            })
+            .await;
+
         loop {
             let control_future = async { self.handle.recv().await.map(Incoming::Control) };
             let notify_future = async {
@@ -185,14 +216,74 @@ impl Runtime {
                             log::warn!("received control for unknown stream {:?}", stream_id);
                         }
                     }
+                    AudioControl::MeterSubscribe {
+                        stream_id,
+                        interval,
+                    } => match self.open_meter(stream_id) {
+                        Ok(Some(meter)) => {
+                            let meter = Meter::new(stream_id, meter, interval, &self.event_tx);
+                            self.meters.insert(stream_id, meter);
+                        }
+                        Ok(None) => {
+                            log::warn!(
+                                "received meter subscription for unknown stream {:?}",
+                                stream_id
+                            );
+                        }
+                        Err(e) => log::warn!("could not meter {:?}: {}", stream_id, e),
+                    },
+                    AudioControl::MeterUnsubscribe { stream_id } => {
+                        self.meters.remove(&stream_id);
+                    }
+                    AudioControl::SetDefaultDevice { stream_id } => {
+                        match self.devices.get(&stream_id) {
+                            Some(device) => {
+                                let endpoint_id = device.endpoint_id()?;
+                                // The notifier reports the new default device.
+                                if let Err(e) = com::set_default_endpoint(&endpoint_id) {
+                                    log::warn!(
+                                        "could not set the default device to {}: {}",
+                                        endpoint_id,
+                                        e
+                                    );
+                                }
+                            }
+                            None => {
+                                log::warn!(
+                                    "{:?} is not a device, can't make it the default",
+                                    stream_id
+                                );
+                            }
+                        }
+                    }
+                    AudioControl::Media(key) => {
+                        press_media_key(key);
+                    }
+                    AudioControl::MediaControl {
+                        stream_ids,
+                        media_control,
+                    } => {
+                        let executables: Vec<String> = stream_ids
+                            .iter()
+                            .filter_map(|stream_id| self.sessions.get(stream_id))
+                            .filter_map(|session| get_executable_path(&session.session_control))
+                            .collect();
+                        match media::control_executables(&executables, media_control) {
+                            Ok(0) => log::debug!("no media player in {:?}", executables),
+                            Ok(_) => {}
+                            Err(e) => log::warn!("could not control media players: {}", e),
+                        }
+                    }
                 },
                 Some(Incoming::Notify(notify_message)) => match notify_message {
                     NotifyEvent::DeviceAdded(device_id) => {
                         let ll_device = self.device_enumerator.get_device(device_id.as_winstr())?;
-                        self.add_device(ll_device).await?;
+                        let direction = self.device_direction(&device_id)?;
+                        self.add_device(ll_device, direction).await?;
                     }
                     NotifyEvent::DeviceRemoved(device_id) => {
                         if let Some((stream_id, _)) = self.device_ids.remove_by_right(&device_id) {
+                            self.meters.remove(&stream_id);
                             self.handle
                                 .send(AudioEvent::StreamClosed { stream_id })
                                 .await;
@@ -235,15 +326,21 @@ impl Runtime {
                                 {
                                     let session = AudioSession::new(
                                         parent_stream_id,
+                                        parent_device.endpoint_id()?,
+                                        parent_device.direction,
                                         session_control,
                                         self.event_tx.clone(),
                                     )?;
                                     let stream_id = session.stream_id();
                                     let stream_info = session.stream_info()?;
                                     let process_id = session.process_id()?;
+                                    // Only playing applications follow the window focus.
+                                    if session.direction == Direction::Output {
+                                        self.process_ids.insert(stream_id, process_id);
+                                        self.focus_stale = true;
+                                    }
                                     self.sessions.insert(stream_id, session);
                                     self.session_ids.insert(stream_id, session_id);
-                                    self.process_ids.insert(stream_id, process_id);
                                     self.handle
                                         .send(AudioEvent::StreamOpened {
                                             stream_id,
@@ -257,29 +354,69 @@ impl Runtime {
                     NotifyEvent::SessionDisconnected(stream_id) => {
                         if self.sessions.remove(&stream_id).is_some() {
                             self.session_ids.remove_by_left(&stream_id);
+                            self.meters.remove(&stream_id);
                             self.process_ids.remove_by_left(&stream_id);
+                            self.focus_stale = true;
                             self.handle
                                 .send(AudioEvent::StreamClosed { stream_id })
                                 .await;
                         };
                     }
-                    NotifyEvent::DefaultDeviceChanged(device_id) => {
+                    NotifyEvent::DefaultDeviceChanged(direction, device_id) => {
                         let stream_id = self.device_ids.get_by_right(&device_id).cloned();
-                        self.handle
-                            .send(AudioEvent::DefaultDeviceChanged { stream_id })
-                            .await;
+                        let event = match direction {
+                            Direction::Output => AudioEvent::DefaultDeviceChanged { stream_id },
+                            Direction::Input => AudioEvent::DefaultInputDeviceChanged { stream_id },
+                        };
+                        self.handle.send(event).await;
+                    }
+                    NotifyEvent::PollMeter(stream_id) => {
+                        let peak = match self.meters.get(&stream_id) {
+                            Some(meter) => meter.meter.peak(),
+                            // It was unsubscribed in the meantime.
+                            None => continue,
+                        };
+                        match peak {
+                            Ok(peak) => {
+                                self.handle
+                                    .send(AudioEvent::StreamEvent {
+                                        stream_id,
+                                        stream_event: StreamEvent::Peak(peak),
+                                    })
+                                    .await;
+                            }
+                            Err(e) => {
+                                // The device or session may be gone, which is reported
+                                // separately.
+                                log::debug!("could not read the peak of {:?}: {}", stream_id, e);
+                                self.meters.remove(&stream_id);
+                            }
+                        }
                     }
                     NotifyEvent::PollFocus => {
                         let mut process_id = 0;
                         unsafe {
                             GetWindowThreadProcessId(GetForegroundWindow(), &mut process_id);
                         }
-                        let new_focus = self.process_ids.get_by_right(&process_id).cloned();
-                        if self.window_focus != new_focus {
+                        // Resolving can read the whole process tree, so it's only done when
+                        // something changed.
+                        if self.focused_process == Some(process_id) && !self.focus_stale {
+                            continue;
+                        }
+                        let process_changed = self.focused_process != Some(process_id);
+                        self.focused_process = Some(process_id);
+                        self.focus_stale = false;
+                        let new_focus = self.resolve_focus(process_id);
+                        // Focus rules also match windows that have no stream.
+                        if self.window_focus != new_focus || process_changed {
                             self.window_focus = new_focus;
                             self.handle
                                 .send(AudioEvent::WindowFocusChanged {
                                     stream_id: self.window_focus,
+                                    application: Some(Application {
+                                        executable: ProcessTree::executable(process_id),
+                                        name: None,
+                                    }),
                                 })
                                 .await;
                         }
@@ -292,19 +429,66 @@ impl Runtime {
         Ok(())
     }
 
-    async fn add_device(&mut self, ll_device: Device) -> windows::Result<()> {
-        let device_info = DeviceInfo::new(&ll_device)?;
-        if self.device_ids.contains_right(&device_info.id) {
-            return Ok(());
+    fn resolve_focus(&self, focused: ProcessId) -> Option<StreamId> {
+        if let Some(&stream_id) = self.process_ids.get_by_right(&focused) {
+            return Some(stream_id);
         }
-        log::debug!("Registering device {:?}", device_info);
-        let device = match AudioDevice::new(ll_device.clone(), self.event_tx.clone()).await {
+        let candidates: Vec<ProcessId> = self.process_ids.right_values().copied().collect();
+        let tree = match ProcessTree::snapshot() {
             Ok(x) => x,
             Err(e) => {
-                log::warn!("could not open audio device: {}", e);
-                return Ok(());
+                log::warn!("could not read the running processes: {}", e);
+                return None;
             }
         };
+        tree.resolve(focused, &candidates)
+            .and_then(|process_id| self.process_ids.get_by_right(&process_id).cloned())
+    }
+
+    /// The meter of a device or session, or `None` if the stream is unknown.
+    fn open_meter(&self, stream_id: StreamId) -> windows::Result<Option<com::AudioMeter>> {
+        if let Some(device) = self.devices.get(&stream_id) {
+            com::AudioMeter::device(&device.endpoint_id()?).map(Some)
+        } else if let Some(session) = self.sessions.get(&stream_id) {
+            com::AudioMeter::session(
+                &session.parent_endpoint_id,
+                &session
+                    .session_control
+                    .get_session_identifier()?
+                    .to_string_lossy(),
+            )
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Whether a newly-added device is a render or a capture endpoint.
+    fn device_direction(&self, device_id: &DeviceId) -> windows::Result<Direction> {
+        let capture_devices = self
+            .device_enumerator
+            .enum_audio_endpoints(DataFlowMask::Capture, DeviceStateMask::all())?;
+        for ll_device in &capture_devices {
+            if ll_device.get_id()? == device_id.0 {
+                return Ok(Direction::Input);
+            }
+        }
+        Ok(Direction::Output)
+    }
+
+    async fn add_device(&mut self, ll_device: Device, direction: Direction) -> windows::Result<()> {
+        let device_info = DeviceInfo::new(&ll_device)?;
+        if self.device_ids.contains_right(&device_info.id) {
+            return Ok(());
+        }
+        log::debug!("Registering device {:?}", device_info);
+        let device =
+            match AudioDevice::new(ll_device.clone(), direction, self.event_tx.clone()).await {
+                Ok(x) => x,
+                Err(e) => {
+                    log::warn!("could not open audio device: {}", e);
+                    return Ok(());
+                }
+            };
         let stream_id = device.stream_id();
         self.handle
             .send(AudioEvent::StreamOpened {
@@ -331,12 +515,44 @@ enum Incoming {
 impl Incoming {
     fn suppress_log(&self) -> bool {
         match self {
-            Self::Notify(NotifyEvent::PollFocus) => true,
+            Self::Notify(NotifyEvent::PollFocus) | Self::Notify(NotifyEvent::PollMeter(_)) => true,
             _ => false,
         }
     }
 }
 
+/// A subscribed peak meter, which is read every time its task asks for it. Windows reports the
+/// peak of the last few milliseconds, so short peaks between two reads are missed.
+struct Meter {
+    meter: com::AudioMeter,
+    /// Stops polling when the meter is dropped.
+    _poll_task: Task<()>,
+}
+
+impl Meter {
+    fn new(
+        stream_id: StreamId,
+        meter: com::AudioMeter,
+        interval: Duration,
+        event_tx: &Sender<NotifyEvent>,
+    ) -> Self {
+        let event_tx = event_tx.clone();
+        let poll_task = smol::spawn(async move {
+            loop {
+                Timer::after(interval).await;
+                let result = event_tx.send(NotifyEvent::PollMeter(stream_id)).await;
+                if result.is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            meter,
+            _poll_task: poll_task,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct DeviceId(WinString);
 
@@ -415,13 +631,40 @@ impl NotificationClient for AudioNotifier {
         role: DeviceRole,
         device_id: &WinStr,
     ) -> windows::Result<()> {
-        if data_flow == DataFlow::Render && role == DeviceRole::Console {
-            self.send(NotifyEvent::DefaultDeviceChanged(device_id.into()));
+        if role != DeviceRole::Console {
+            return Ok(());
+        }
+        if data_flow == DataFlow::Render {
+            self.send(NotifyEvent::DefaultDeviceChanged(
+                Direction::Output,
+                device_id.into(),
+            ));
+        } else if data_flow == DataFlow::Capture {
+            self.send(NotifyEvent::DefaultDeviceChanged(
+                Direction::Input,
+                device_id.into(),
+            ));
         }
         Ok(())
     }
 }
 
+/// Sends a media key press to the system, the same as a keyboard would.
+fn press_media_key(key: MediaKey) {
+    // Virtual-key codes from WinUser.h.
+    let virtual_key: u8 = match key {
+        MediaKey::Next => 0xB0,
+        MediaKey::Previous => 0xB1,
+        MediaKey::Stop => 0xB2,
+        MediaKey::PlayPause => 0xB3,
+    };
+    const KEYEVENTF_KEYUP: u32 = 0x0002;
+    unsafe {
+        keybd_event(virtual_key, 0, KEYBD_EVENT_FLAGS(0), 0);
+        keybd_event(virtual_key, 0, KEYBD_EVENT_FLAGS(KEYEVENTF_KEYUP), 0);
+    }
+}
+
 #[derive(Debug)]
 enum NotifyEvent {
     DeviceAdded(DeviceId),
@@ -430,12 +673,15 @@ enum NotifyEvent {
     StreamStateChanged(StreamId, StreamState),
     SessionCreated(StreamId, SessionId),
     SessionDisconnected(StreamId),
-    DefaultDeviceChanged(DeviceId),
+    DefaultDeviceChanged(Direction, DeviceId),
     PollFocus,
+    PollMeter(StreamId),
 }
 
 struct AudioSession {
     parent_stream_id: StreamId,
+    parent_endpoint_id: String,
+    direction: Direction,
     stream_id: StreamId,
     session_control: AudioSessionControl2,
     volume_control: SimpleAudioVolume,
@@ -445,6 +691,8 @@ struct AudioSession {
 impl AudioSession {
     fn new(
         parent_stream_id: StreamId,
+        parent_endpoint_id: String,
+        direction: Direction,
         session_control: AudioSessionControl2,
         event_tx: Sender<NotifyEvent>,
     ) -> windows::Result<Self> {
@@ -461,6 +709,8 @@ impl AudioSession {
 
         Ok(Self {
             parent_stream_id,
+            parent_endpoint_id,
+            direction,
             stream_id,
             session_control,
             volume_control,
@@ -503,10 +753,24 @@ impl AudioSession {
         })
     }
 
+    fn identity(&self) -> windows::Result<StreamIdentity> {
+        Ok(StreamIdentity::Session {
+            endpoint_id: self.parent_endpoint_id.clone(),
+            session_identifier: Some(
+                self.session_control
+                    .get_session_identifier()?
+                    .to_string_lossy(),
+            ),
+            executable: get_executable_path(&self.session_control),
+        })
+    }
+
     fn stream_info(&self) -> windows::Result<StreamInfo> {
         Ok(StreamInfoBuilder::new(self.name()?)
             .with_initial_state(self.stream_state()?)
             .with_parent(self.parent_stream_id)
+            .with_identity(self.identity()?)
+            .with_direction(self.direction)
             .build())
     }
 
@@ -533,6 +797,7 @@ impl AudioSession {
 
 struct AudioDevice {
     stream_id: StreamId,
+    direction: Direction,
     ll_device: Device,
     properties: PropertyStore,
     volume: AudioEndpointVolume,
@@ -542,7 +807,11 @@ struct AudioDevice {
 }
 
 impl AudioDevice {
-    async fn new(ll_device: Device, event_tx: Sender<NotifyEvent>) -> windows::Result<Self> {
+    async fn new(
+        ll_device: Device,
+        direction: Direction,
+        event_tx: Sender<NotifyEvent>,
+    ) -> windows::Result<Self> {
         let properties = ll_device.open_property_store(StorageAccessMode::Read)?;
         let volume = ll_device.activate_audio_endpoint_volume()?;
         let session_manager = ll_device.activate_audio_session_manager2()?;
@@ -577,6 +846,7 @@ impl AudioDevice {
             properties,
             volume,
             stream_id,
+            direction,
             session_manager,
             _device_notifier: device_notifier,
             _session_notifier: session_notifier,
@@ -589,10 +859,30 @@ impl AudioDevice {
             .map(string_prop)
     }
 
+    /// The form factor of the endpoint, like "headphone" or "speaker", named like the
+    /// PulseAudio backend does.
+    fn role(&self) -> Option<String> {
+        match self.properties.get_value(&ENDPOINT_FORM_FACTOR) {
+            Ok(Property::U32(form_factor)) => form_factor_name(form_factor).map(String::from),
+            Ok(property) => {
+                log::debug!("invalid form factor {:?}", property);
+                None
+            }
+            Err(e) => {
+                log::debug!("could not get the form factor: {}", e);
+                None
+            }
+        }
+    }
+
     fn id(&self) -> windows::Result<DeviceId> {
         self.ll_device.get_id().map(DeviceId)
     }
 
+    fn endpoint_id(&self) -> windows::Result<String> {
+        Ok(self.ll_device.get_id()?.to_string_lossy())
+    }
+
     fn volume(&self) -> windows::Result<f32> {
         self.volume.get_master_volume_level_scalar()
     }
@@ -640,6 +930,11 @@ impl AudioDevice {
     fn stream_info(&self) -> windows::Result<StreamInfo> {
         Ok(StreamInfoBuilder::new(self.name()?)
             .with_initial_state(self.stream_state()?)
+            .with_identity(StreamIdentity::Device {
+                endpoint_id: self.endpoint_id()?,
+                role: self.role(),
+            })
+            .with_direction(self.direction)
             .build())
     }
 
@@ -659,6 +954,35 @@ impl AudioDevice {
     }
 }
 
+/// `PKEY_AudioEndpoint_FormFactor`, which holds an `EndpointFormFactor`.
+const ENDPOINT_FORM_FACTOR: PropertyKey = PropertyKey {
+    fmtid: Guid::from_values(
+        0x1da5d803,
+        0xd492,
+        0x4edd,
+        [0x8c, 0x23, 0xe0, 0xc0, 0xff, 0xee, 0x7f, 0x0e],
+    ),
+    pid: 0,
+};
+
+/// Names the values of `EndpointFormFactor`.
+fn form_factor_name(form_factor: u32) -> Option<&'static str> {
+    let name = match form_factor {
+        0 => "network",
+        1 => "speaker",
+        2 => "line",
+        3 => "headphone",
+        4 => "microphone",
+        5 => "headset",
+        6 => "handset",
+        7 => "digital",
+        8 => "spdif",
+        9 => "hdmi",
+        _ => return None,
+    };
+    Some(name)
+}
+
 fn string_prop(property: Property) -> String {
     match property {
         Property::Str(winstring) => winstring.to_string_lossy(),
@@ -749,6 +1073,17 @@ impl AudioSessionEvents for SessionNotifier {
     }
 }
 
+/// Extracts the executable path from the session identifier, which looks like
+/// `{endpoint}|\Device\HarddiskVolume1\path\to\app.exe%b{instance}`.
+fn get_executable_path(session_control: &AudioSessionControl2) -> Option<String> {
+    let string = session_control
+        .get_session_identifier()
+        .ok()?
+        .to_string_lossy();
+    let path = string.rsplit_once('%')?.0.split_once('|')?.1;
+    Some(path.to_string())
+}
+
 fn get_process_name(session_control: &AudioSessionControl2) -> Option<String> {
     let string = session_control
         .get_session_identifier()
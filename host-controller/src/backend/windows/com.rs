@@ -0,0 +1,247 @@
+//! Core Audio calls that win32-coreaudio doesn't wrap, made through the raw COM vtables.
+//!
+//! The interfaces are declared by their method indices, which are fixed by the ABI, so this
+//! doesn't need any more generated bindings.
+
+use std::{ffi::c_void, ptr};
+use windows::{Guid, HRESULT};
+
+const CLSID_MM_DEVICE_ENUMERATOR: Guid = Guid::from_values(
+    0xbcde0395,
+    0xe52f,
+    0x467c,
+    [0x8e, 0x3d, 0xc4, 0x57, 0x92, 0x91, 0x69, 0x2e],
+);
+const IID_IMM_DEVICE_ENUMERATOR: Guid = Guid::from_values(
+    0xa95664d2,
+    0x9614,
+    0x4f35,
+    [0xa7, 0x46, 0xde, 0x8d, 0xb6, 0x36, 0x17, 0xe6],
+);
+
+/// `CPolicyConfigClient`, which the Sound control panel uses to change the default devices. It is
+/// not documented, but has stayed the same since Windows 7.
+const CLSID_POLICY_CONFIG_CLIENT: Guid = Guid::from_values(
+    0x870af99c,
+    0x171d,
+    0x4f9e,
+    [0xaf, 0x0d, 0xe6, 0x3d, 0xf4, 0x0c, 0x2b, 0xc9],
+);
+const IID_IPOLICY_CONFIG: Guid = Guid::from_values(
+    0xf8679f50,
+    0x850a,
+    0x41cf,
+    [0x9c, 0x72, 0x43, 0x0f, 0x29, 0x02, 0x90, 0xc8],
+);
+
+const IID_IAUDIO_METER_INFORMATION: Guid = Guid::from_values(
+    0xc02216f6,
+    0x8c67,
+    0x4b5b,
+    [0x9d, 0x00, 0xd0, 0x08, 0xe7, 0x3e, 0x00, 0x64],
+);
+const IID_IAUDIO_SESSION_MANAGER2: Guid = Guid::from_values(
+    0x77aa99a0,
+    0x1bd6,
+    0x484f,
+    [0x8b, 0xc7, 0x2c, 0x65, 0x4c, 0x9a, 0x9b, 0x6f],
+);
+const IID_IAUDIO_SESSION_CONTROL2: Guid = Guid::from_values(
+    0xbfb7ff88,
+    0x7239,
+    0x4fc9,
+    [0x8f, 0xa2, 0x07, 0xc9, 0x50, 0xbe, 0x9c, 0x6d],
+);
+
+/// The values of `ERole`: `eConsole`, `eMultimedia` and `eCommunications`.
+const ROLES: [u32; 3] = [0, 1, 2];
+
+const CLSCTX_ALL: u32 = 0x17;
+
+// Vtable indices, which count the three methods of `IUnknown`.
+const IUNKNOWN_QUERY_INTERFACE: usize = 0;
+const IUNKNOWN_RELEASE: usize = 2;
+const IMM_DEVICE_ENUMERATOR_GET_DEVICE: usize = 5;
+const IMM_DEVICE_ACTIVATE: usize = 3;
+const IAUDIO_SESSION_MANAGER2_GET_SESSION_ENUMERATOR: usize = 5;
+const IAUDIO_SESSION_ENUMERATOR_GET_COUNT: usize = 3;
+const IAUDIO_SESSION_ENUMERATOR_GET_SESSION: usize = 4;
+const IAUDIO_SESSION_CONTROL2_GET_SESSION_IDENTIFIER: usize = 12;
+const IAUDIO_METER_INFORMATION_GET_PEAK_VALUE: usize = 3;
+const IPOLICY_CONFIG_SET_DEFAULT_ENDPOINT: usize = 13;
+
+#[link(name = "ole32")]
+extern "system" {
+    fn CoCreateInstance(
+        clsid: *const Guid,
+        outer: *mut c_void,
+        context: u32,
+        iid: *const Guid,
+        object: *mut *mut c_void,
+    ) -> HRESULT;
+    fn CoTaskMemFree(memory: *mut c_void);
+}
+
+/// An interface pointer, which is released when it is dropped.
+struct ComObject(*mut c_void);
+
+impl ComObject {
+    unsafe fn create(clsid: &Guid, iid: &Guid) -> windows::Result<Self> {
+        let mut object = ptr::null_mut();
+        CoCreateInstance(clsid, ptr::null_mut(), CLSCTX_ALL, iid, &mut object).ok()?;
+        Ok(Self(object))
+    }
+
+    /// The method at `index` in the vtable, which has to be called with `self.0` as the first
+    /// argument. `F` must be the method's exact signature.
+    unsafe fn method<F: Copy>(&self, index: usize) -> F {
+        let vtable = *(self.0 as *const *const usize);
+        std::mem::transmute_copy(&*vtable.add(index))
+    }
+
+    unsafe fn query_interface(&self, iid: &Guid) -> windows::Result<Self> {
+        let query_interface: unsafe extern "system" fn(
+            *mut c_void,
+            *const Guid,
+            *mut *mut c_void,
+        ) -> HRESULT = self.method(IUNKNOWN_QUERY_INTERFACE);
+        let mut object = ptr::null_mut();
+        query_interface(self.0, iid, &mut object).ok()?;
+        Ok(Self(object))
+    }
+
+    /// Activates an interface of an `IMMDevice`.
+    unsafe fn activate(&self, iid: &Guid) -> windows::Result<Self> {
+        let activate: unsafe extern "system" fn(
+            *mut c_void,
+            *const Guid,
+            u32,
+            *mut c_void,
+            *mut *mut c_void,
+        ) -> HRESULT = self.method(IMM_DEVICE_ACTIVATE);
+        let mut object = ptr::null_mut();
+        activate(self.0, iid, CLSCTX_ALL, ptr::null_mut(), &mut object).ok()?;
+        Ok(Self(object))
+    }
+}
+
+impl Drop for ComObject {
+    fn drop(&mut self) {
+        unsafe {
+            let release: unsafe extern "system" fn(*mut c_void) -> u32 =
+                self.method(IUNKNOWN_RELEASE);
+            release(self.0);
+        }
+    }
+}
+
+/// The `IMMDevice` of an endpoint.
+unsafe fn get_device(endpoint_id: &str) -> windows::Result<ComObject> {
+    let endpoint_id = wide(endpoint_id);
+    let enumerator = ComObject::create(&CLSID_MM_DEVICE_ENUMERATOR, &IID_IMM_DEVICE_ENUMERATOR)?;
+    let get_device: unsafe extern "system" fn(
+        *mut c_void,
+        *const u16,
+        *mut *mut c_void,
+    ) -> HRESULT = enumerator.method(IMM_DEVICE_ENUMERATOR_GET_DEVICE);
+    let mut device = ptr::null_mut();
+    get_device(enumerator.0, endpoint_id.as_ptr(), &mut device).ok()?;
+    Ok(ComObject(device))
+}
+
+/// An `IAudioMeterInformation`, which measures the peaks of a device or session.
+pub struct AudioMeter(ComObject);
+
+impl AudioMeter {
+    /// Measures everything that is played or recorded on the endpoint.
+    pub fn device(endpoint_id: &str) -> windows::Result<Self> {
+        unsafe {
+            let device = get_device(endpoint_id)?;
+            Ok(Self(device.activate(&IID_IAUDIO_METER_INFORMATION)?))
+        }
+    }
+
+    /// Measures a session of the endpoint, if it still exists.
+    pub fn session(endpoint_id: &str, session_identifier: &str) -> windows::Result<Option<Self>> {
+        unsafe {
+            let device = get_device(endpoint_id)?;
+            let manager = device.activate(&IID_IAUDIO_SESSION_MANAGER2)?;
+            let get_session_enumerator: unsafe extern "system" fn(
+                *mut c_void,
+                *mut *mut c_void,
+            ) -> HRESULT = manager.method(IAUDIO_SESSION_MANAGER2_GET_SESSION_ENUMERATOR);
+            let mut enumerator = ptr::null_mut();
+            get_session_enumerator(manager.0, &mut enumerator).ok()?;
+            let enumerator = ComObject(enumerator);
+
+            let get_count: unsafe extern "system" fn(*mut c_void, *mut i32) -> HRESULT =
+                enumerator.method(IAUDIO_SESSION_ENUMERATOR_GET_COUNT);
+            let get_session: unsafe extern "system" fn(
+                *mut c_void,
+                i32,
+                *mut *mut c_void,
+            ) -> HRESULT = enumerator.method(IAUDIO_SESSION_ENUMERATOR_GET_SESSION);
+            let mut count = 0;
+            get_count(enumerator.0, &mut count).ok()?;
+            for index in 0..count {
+                let mut session = ptr::null_mut();
+                get_session(enumerator.0, index, &mut session).ok()?;
+                let session = ComObject(session).query_interface(&IID_IAUDIO_SESSION_CONTROL2)?;
+
+                let get_session_identifier: unsafe extern "system" fn(
+                    *mut c_void,
+                    *mut *mut u16,
+                ) -> HRESULT = session.method(IAUDIO_SESSION_CONTROL2_GET_SESSION_IDENTIFIER);
+                let mut identifier = ptr::null_mut();
+                get_session_identifier(session.0, &mut identifier).ok()?;
+                let matches = from_wide(identifier) == session_identifier;
+                CoTaskMemFree(identifier as *mut c_void);
+                if matches {
+                    return Ok(Some(Self(
+                        session.query_interface(&IID_IAUDIO_METER_INFORMATION)?,
+                    )));
+                }
+            }
+            Ok(None)
+        }
+    }
+
+    /// The highest peak of all channels, from 0 to 1.
+    pub fn peak(&self) -> windows::Result<f32> {
+        unsafe {
+            let get_peak_value: unsafe extern "system" fn(*mut c_void, *mut f32) -> HRESULT =
+                self.0.method(IAUDIO_METER_INFORMATION_GET_PEAK_VALUE);
+            let mut peak = 0.0;
+            get_peak_value((self.0).0, &mut peak).ok()?;
+            Ok(peak)
+        }
+    }
+}
+
+/// Makes the endpoint the default device for all roles, like the Sound control panel does.
+pub fn set_default_endpoint(endpoint_id: &str) -> windows::Result<()> {
+    let endpoint_id = wide(endpoint_id);
+    unsafe {
+        let policy_config = ComObject::create(&CLSID_POLICY_CONFIG_CLIENT, &IID_IPOLICY_CONFIG)?;
+        let set_default_endpoint: unsafe extern "system" fn(
+            *mut c_void,
+            *const u16,
+            u32,
+        ) -> HRESULT = policy_config.method(IPOLICY_CONFIG_SET_DEFAULT_ENDPOINT);
+        for &role in &ROLES {
+            set_default_endpoint(policy_config.0, endpoint_id.as_ptr(), role).ok()?;
+        }
+    }
+    Ok(())
+}
+
+/// A nul-terminated UTF-16 string.
+fn wide(string: &str) -> Vec<u16> {
+    string.encode_utf16().chain(Some(0)).collect()
+}
+
+/// Reads a nul-terminated UTF-16 string.
+unsafe fn from_wide(string: *const u16) -> String {
+    let length = (0..).take_while(|&i| *string.add(i) != 0).count();
+    String::from_utf16_lossy(std::slice::from_raw_parts(string, length))
+}
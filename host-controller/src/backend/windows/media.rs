@@ -0,0 +1,99 @@
+//! Controls media players through the system media transport controls, which Windows shows in
+//! its volume flyout. Players are matched to audio sessions by the app that they belong to.
+
+use crate::action::MediaKey;
+use crate::audio::MediaControl;
+use crate::bindings::Windows::{
+    Foundation::TimeSpan,
+    Media::Control::{
+        GlobalSystemMediaTransportControlsSession, GlobalSystemMediaTransportControlsSessionManager,
+    },
+};
+
+/// Controls every player that belongs to one of the executables, which are paths or file names.
+/// Returns how many players were controlled.
+pub fn control_executables(
+    executables: &[String],
+    media_control: MediaControl,
+) -> windows::Result<usize> {
+    let manager = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?.get()?;
+    let sessions = manager.GetSessions()?;
+    let mut controlled = 0;
+    for index in 0..sessions.Size()? {
+        let session = sessions.GetAt(index)?;
+        let app = session.SourceAppUserModelId()?.to_string();
+        if !executables
+            .iter()
+            .any(|executable| app_matches(&app, executable))
+        {
+            continue;
+        }
+        if let Err(e) = control(&session, media_control) {
+            log::warn!("could not control {}: {}", app, e);
+        }
+        controlled += 1;
+    }
+    Ok(controlled)
+}
+
+fn control(
+    session: &GlobalSystemMediaTransportControlsSession,
+    media_control: MediaControl,
+) -> windows::Result<()> {
+    let success = match media_control {
+        MediaControl::Key(MediaKey::PlayPause) => session.TryTogglePlayPauseAsync()?.get()?,
+        MediaControl::Key(MediaKey::Stop) => session.TryStopAsync()?.get()?,
+        MediaControl::Key(MediaKey::Next) => session.TrySkipNextAsync()?.get()?,
+        MediaControl::Key(MediaKey::Previous) => session.TrySkipPreviousAsync()?.get()?,
+        MediaControl::Seek(millis) => {
+            let timeline = session.GetTimelineProperties()?;
+            // Positions are in units of 100 nanoseconds.
+            let TimeSpan { Duration: position } = timeline.Position()?;
+            let TimeSpan { Duration: start } = timeline.StartTime()?;
+            let TimeSpan { Duration: end } = timeline.EndTime()?;
+            let position = (position + millis * 10_000).clamp(start, end.max(start));
+            session.TryChangePlaybackPositionAsync(position)?.get()?
+        }
+    };
+    if !success {
+        log::debug!("the player refused {:?}", media_control);
+    }
+    Ok(())
+}
+
+/// Whether a player's app belongs to the executable. Desktop apps are identified by their
+/// executable's file name, or by an app ID that is usually named after it, like `Chrome` for
+/// `chrome.exe`.
+fn app_matches(app_user_model_id: &str, executable: &str) -> bool {
+    let file_name = executable
+        .rsplit(|c| c == '\\' || c == '/')
+        .next()
+        .unwrap_or(executable);
+    let stem = file_name
+        .rsplit_once('.')
+        .map(|(stem, _extension)| stem)
+        .unwrap_or(file_name);
+    app_user_model_id.eq_ignore_ascii_case(file_name)
+        || app_user_model_id.eq_ignore_ascii_case(stem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apps() {
+        let spotify = r"\Device\HarddiskVolume3\Users\me\AppData\Roaming\Spotify\Spotify.exe";
+        assert!(app_matches("Spotify.exe", spotify));
+        assert!(app_matches("spotify", spotify));
+        assert!(app_matches(
+            "Chrome",
+            r"C:\Program Files\Google\Chrome\chrome.exe"
+        ));
+        assert!(!app_matches("Chrome", spotify));
+        assert!(!app_matches(
+            "Microsoft.ZuneMusic_8wekyb3d8bbwe!Microsoft.ZuneMusic",
+            spotify
+        ));
+    }
+}
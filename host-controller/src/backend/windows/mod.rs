@@ -1,3 +1,5 @@
 mod audio;
+mod com;
+mod media;
 
 pub use self::audio::WindowsAudioBackend;
@@ -0,0 +1,233 @@
+//! Tracks the focused window on X11, through the `_NET_ACTIVE_WINDOW` property that EWMH window
+//! managers keep on the root window, and the `_NET_WM_PID` of that window.
+//!
+//! On Wayland, this only sees windows that run through XWayland. Compositors that support it are
+//! watched through the [`wayland`](super::wayland) module instead.
+
+use std::error::Error;
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        xproto::{AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, EventMask, Window},
+        Event,
+    },
+    rust_connection::RustConnection,
+};
+
+type ProcessId = u32;
+
+pub struct FocusWatcher {
+    connection: RustConnection,
+    root: Window,
+    net_active_window: u32,
+    net_wm_pid: u32,
+}
+
+impl FocusWatcher {
+    /// Connects to the display in `$DISPLAY`.
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        Self::connect(None)
+    }
+
+    /// Connects to a display, like `:1`.
+    pub fn with_display(display: &str) -> Result<Self, Box<dyn Error>> {
+        Self::connect(Some(display))
+    }
+
+    fn connect(display: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let (connection, screen) = x11rb::connect(display)?;
+        let root = connection.setup().roots[screen].root;
+        let net_active_window = connection
+            .intern_atom(false, b"_NET_ACTIVE_WINDOW")?
+            .reply()?
+            .atom;
+        let net_wm_pid = connection.intern_atom(false, b"_NET_WM_PID")?.reply()?.atom;
+        connection.change_window_attributes(
+            root,
+            &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+        )?;
+        connection.flush()?;
+        Ok(Self {
+            connection,
+            root,
+            net_active_window,
+            net_wm_pid,
+        })
+    }
+
+    /// The process of the focused window, if there is one and it says which process it belongs
+    /// to.
+    pub fn focused_process(&self) -> Result<Option<ProcessId>, Box<dyn Error>> {
+        let window = match self.get_cardinal(self.root, self.net_active_window, AtomEnum::WINDOW)? {
+            Some(window) if window != x11rb::NONE => window,
+            _ => return Ok(None),
+        };
+        // The window may be gone already.
+        match self.get_cardinal(window, self.net_wm_pid, AtomEnum::CARDINAL) {
+            Ok(process_id) => Ok(process_id),
+            Err(e) => {
+                log::debug!("could not get the process of window {}: {}", window, e);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Calls `on_change` with the focused process, and again every time it changes. This blocks
+    /// until the connection fails, so it should run on its own thread.
+    pub fn run(self, mut on_change: impl FnMut(Option<ProcessId>)) -> Result<(), Box<dyn Error>> {
+        let mut focus = self.focused_process()?;
+        on_change(focus);
+        loop {
+            if let Event::PropertyNotify(event) = self.connection.wait_for_event()? {
+                if event.window != self.root || event.atom != self.net_active_window {
+                    continue;
+                }
+                let new_focus = self.focused_process()?;
+                if new_focus != focus {
+                    focus = new_focus;
+                    on_change(focus);
+                }
+            }
+        }
+    }
+
+    fn get_cardinal(
+        &self,
+        window: Window,
+        property: u32,
+        property_type: AtomEnum,
+    ) -> Result<Option<u32>, Box<dyn Error>> {
+        let reply = self
+            .connection
+            .get_property(false, window, property, property_type, 0, 1)?
+            .reply()?;
+        Ok(reply.value32().and_then(|mut values| values.next()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+        sync::mpsc,
+        time::Duration,
+    };
+    use x11rb::{
+        protocol::xproto::{CreateWindowAux, PropMode, WindowClass},
+        wrapper::ConnectionExt as _,
+        COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT,
+    };
+
+    /// A virtual X server, which is stopped when this is dropped.
+    struct Xvfb {
+        server: Child,
+        display: String,
+    }
+
+    impl Xvfb {
+        fn start() -> Self {
+            let mut server = Command::new("Xvfb")
+                .args(&["-displayfd", "1"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .expect("could not start Xvfb");
+            let mut display = String::new();
+            BufReader::new(server.stdout.take().unwrap())
+                .read_line(&mut display)
+                .unwrap();
+            Self {
+                server,
+                display: format!(":{}", display.trim()),
+            }
+        }
+    }
+
+    impl Drop for Xvfb {
+        fn drop(&mut self) {
+            self.server.kill().ok();
+            self.server.wait().ok();
+        }
+    }
+
+    #[test]
+    #[ignore = "needs Xvfb"]
+    fn run_reports_the_focused_process() {
+        let xvfb = Xvfb::start();
+        let (focus_tx, focus_rx) = mpsc::channel();
+        let watcher = FocusWatcher::with_display(&xvfb.display).unwrap();
+        // This stops with an error once the server is stopped.
+        std::thread::spawn(move || {
+            watcher
+                .run(|process_id| {
+                    focus_tx.send(process_id).ok();
+                })
+                .ok();
+        });
+        let next_focus = || focus_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        // There is no window manager to set an active window.
+        assert_eq!(next_focus(), None);
+
+        // Play the window manager.
+        let (connection, screen) = x11rb::connect(Some(&xvfb.display)).unwrap();
+        let root = connection.setup().roots[screen].root;
+        let intern = |name: &[u8]| {
+            connection
+                .intern_atom(false, name)
+                .unwrap()
+                .reply()
+                .unwrap()
+        };
+        let net_active_window = intern(b"_NET_ACTIVE_WINDOW").atom;
+        let net_wm_pid = intern(b"_NET_WM_PID").atom;
+        let window = connection.generate_id().unwrap();
+        connection
+            .create_window(
+                COPY_DEPTH_FROM_PARENT,
+                window,
+                root,
+                0,
+                0,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                COPY_FROM_PARENT,
+                &CreateWindowAux::new(),
+            )
+            .unwrap();
+        connection
+            .change_property32(
+                PropMode::REPLACE,
+                window,
+                net_wm_pid,
+                AtomEnum::CARDINAL,
+                &[4242],
+            )
+            .unwrap();
+        connection
+            .change_property32(
+                PropMode::REPLACE,
+                root,
+                net_active_window,
+                AtomEnum::WINDOW,
+                &[window],
+            )
+            .unwrap();
+        connection.flush().unwrap();
+        assert_eq!(next_focus(), Some(4242));
+
+        connection
+            .change_property32(
+                PropMode::REPLACE,
+                root,
+                net_active_window,
+                AtomEnum::WINDOW,
+                &[x11rb::NONE],
+            )
+            .unwrap();
+        connection.flush().unwrap();
+        assert_eq!(next_focus(), None);
+    }
+}
@@ -0,0 +1,28 @@
+//! The source of time for everything in the core that depends on it, like acceleration, gesture
+//! timeouts and ducking, so it can be replaced in tests.
+
+use smol::Timer;
+use std::{future::Future, pin::Pin, time::Instant};
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+
+    /// Completes once the clock has reached the deadline.
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()>>>;
+}
+
+/// The system's monotonic clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()>>> {
+        Box::pin(async move {
+            Timer::at(deadline).await;
+        })
+    }
+}
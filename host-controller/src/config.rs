@@ -0,0 +1,419 @@
+use crate::{
+    audio::StreamIdentity,
+    gesture::Gestures,
+    pattern::StreamPattern,
+    volume::{Acceleration, VolumeCurve, VolumeLimits, DEFAULT_VOLUME_STEP},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The name of the profile that is used if none is configured.
+pub const DEFAULT_PROFILE: &str = "Default";
+
+/// Persistent configuration, stored on disk as TOML.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The name of the profile whose bindings are currently used.
+    pub active_profile: String,
+    pub profiles: Vec<Profile>,
+    /// Patterns that are offered as bindings in the menu.
+    pub rules: Vec<Rule>,
+    pub auto_profile: AutoProfile,
+    pub removed_device_bindings: RemovedDeviceBindings,
+    /// Settings of individual control devices, which apply in every profile.
+    pub devices: Vec<DeviceSettings>,
+    /// How often the peak levels of bound streams are sent to the control devices, in
+    /// milliseconds. Peak meters are disabled if this is not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meter_interval_ms: Option<u64>,
+    /// Lowers the other streams while a priority stream is audible. Disabled if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ducking: Option<Ducking>,
+    /// What the buttons and encoders of the control devices do.
+    pub gestures: Gestures,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            active_profile: DEFAULT_PROFILE.to_string(),
+            profiles: Vec::new(),
+            rules: Vec::new(),
+            auto_profile: AutoProfile::default(),
+            removed_device_bindings: RemovedDeviceBindings::default(),
+            devices: Vec::new(),
+            meter_interval_ms: None,
+            ducking: None,
+            gestures: Gestures::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The default location of the configuration file, in the user's configuration directory.
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("windowmaster").join("config.toml"))
+    }
+
+    /// Loads the configuration from the given path.
+    ///
+    /// If the file does not exist, the default configuration is returned.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(toml::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Going through `Value` puts plain values before tables, regardless of field order,
+        // which TOML requires.
+        let value = toml::Value::try_from(self)?;
+        fs::write(path, toml::to_string(&value)?)?;
+        Ok(())
+    }
+
+    /// The active profile, if it exists.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.name == self.active_profile)
+    }
+
+    /// The active profile, which is created if it doesn't exist yet.
+    pub fn profile_mut(&mut self) -> &mut Profile {
+        let index = match self
+            .profiles
+            .iter()
+            .position(|profile| profile.name == self.active_profile)
+        {
+            Some(index) => index,
+            None => {
+                self.profiles.push(Profile {
+                    name: self.active_profile.clone(),
+                    bindings: Vec::new(),
+                });
+                self.profiles.len() - 1
+            }
+        };
+        &mut self.profiles[index]
+    }
+
+    pub fn has_profile(&self, name: &str) -> bool {
+        name == self.active_profile || self.profiles.iter().any(|profile| profile.name == name)
+    }
+
+    /// The saved binding targets of the given channel in the active profile.
+    pub fn channel_targets(&self, channel: &ChannelKey) -> &[Target] {
+        self.profile()
+            .map(|profile| profile.channel_targets(channel))
+            .unwrap_or(&[])
+    }
+
+    /// Replaces the saved binding targets of the given channel in the active profile.
+    pub fn set_channel_targets(&mut self, channel: ChannelKey, targets: Vec<Target>) {
+        self.profile_mut().set_channel_targets(channel, targets);
+    }
+
+    /// Removes the saved bindings of the given control device from all profiles.
+    pub fn forget_device(&mut self, device: &str) {
+        for profile in &mut self.profiles {
+            profile
+                .bindings
+                .retain(|bindings| bindings.channel.device != device);
+        }
+    }
+
+    pub fn device_settings(&self, device: &str) -> Option<&DeviceSettings> {
+        self.devices
+            .iter()
+            .find(|settings| settings.device == device)
+    }
+
+    /// The saved bindings and settings of the given channel in the active profile, if there are
+    /// any.
+    pub fn channel_bindings(&self, channel: &ChannelKey) -> Option<&ChannelBindings> {
+        self.profile()
+            .and_then(|profile| profile.channel_bindings(channel))
+    }
+}
+
+/// A named set of channel bindings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub bindings: Vec<ChannelBindings>,
+}
+
+impl Profile {
+    /// The saved binding targets of the given channel.
+    pub fn channel_targets(&self, channel: &ChannelKey) -> &[Target] {
+        self.bindings
+            .iter()
+            .find(|bindings| bindings.channel == *channel)
+            .map(|bindings| bindings.targets.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Replaces the saved binding targets of the given channel.
+    pub fn set_channel_targets(&mut self, channel: ChannelKey, targets: Vec<Target>) {
+        match self
+            .bindings
+            .iter_mut()
+            .find(|bindings| bindings.channel == channel)
+        {
+            Some(bindings) => {
+                bindings.targets = targets;
+            }
+            None => {
+                self.bindings.push(ChannelBindings {
+                    targets,
+                    ..ChannelBindings::new(channel)
+                });
+                self.bindings.sort_by(|a, b| a.channel.cmp(&b.channel));
+            }
+        }
+        // Channels without any bindings or settings don't need to be saved.
+        self.bindings.retain(|bindings| !bindings.is_empty());
+    }
+
+    pub fn channel_bindings(&self, channel: &ChannelKey) -> Option<&ChannelBindings> {
+        self.bindings
+            .iter()
+            .find(|bindings| bindings.channel == *channel)
+    }
+}
+
+/// The saved bindings of a single channel.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelBindings {
+    #[serde(flatten)]
+    pub channel: ChannelKey,
+    #[serde(default)]
+    pub mode: ChannelMode,
+    #[serde(default)]
+    pub volume_mode: VolumeMode,
+    /// Overrides the acceleration of the device for this channel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acceleration: Option<Acceleration>,
+    pub targets: Vec<Target>,
+}
+
+impl ChannelBindings {
+    /// A channel without any bindings, and with the default settings.
+    pub fn new(channel: ChannelKey) -> Self {
+        Self {
+            channel,
+            mode: ChannelMode::default(),
+            volume_mode: VolumeMode::default(),
+            acceleration: None,
+            targets: Vec::new(),
+        }
+    }
+
+    /// Whether there are no bindings, and all settings are at their defaults.
+    pub fn is_empty(&self) -> bool {
+        let default = Self::new(self.channel.clone());
+        self.targets.is_empty()
+            && self.mode == default.mode
+            && self.volume_mode == default.volume_mode
+            && self.acceleration == default.acceleration
+    }
+}
+
+fn default_volume_step() -> f64 {
+    DEFAULT_VOLUME_STEP
+}
+
+fn is_default_volume_step(volume_step: &f64) -> bool {
+    *volume_step == DEFAULT_VOLUME_STEP
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// Settings of a control device.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceSettings {
+    /// The serial number of the control device, or its name if it doesn't have one.
+    pub device: String,
+    /// The acceleration of all channels of the device, unless a channel overrides it.
+    #[serde(default)]
+    pub acceleration: Acceleration,
+}
+
+/// What turning a channel's knob does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChannelMode {
+    /// The knob runs the configured gestures, which control the volume by default.
+    #[default]
+    Volume,
+    /// Turning the knob opens a list of the output devices, and pressing it makes the
+    /// highlighted device the default one.
+    OutputSelect,
+    /// The channel's streams are unmuted while the button is held down, and go back to how
+    /// they were when it is released. Clicks and long presses are not used.
+    PushToTalk,
+    /// Like `PushToTalk`, but the streams are muted while the button is held down.
+    PushToMute,
+}
+
+/// How a channel controls the volume when it is bound to more than one stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VolumeMode {
+    /// Every stream receives the same control, so they all end up at the same volume.
+    #[default]
+    Independent,
+    /// The streams are scaled together, keeping their volumes relative to each other.
+    Group,
+}
+
+/// A stable identifier for a channel, which stays the same across restarts and reconnects.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ChannelKey {
+    /// The serial number of the control device, or its name if it doesn't have one.
+    pub device: String,
+    pub channel: usize,
+}
+
+/// A saved binding of a channel: what it is bound to, and how it controls the volume of those
+/// streams.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Target {
+    #[serde(flatten)]
+    pub kind: TargetKind,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub volume_curve: VolumeCurve,
+    /// How far a single encoder step moves the streams along the volume curve.
+    #[serde(
+        default = "default_volume_step",
+        skip_serializing_if = "is_default_volume_step"
+    )]
+    pub volume_step: f64,
+    /// Keeps the volume of the bound streams within a range.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<VolumeLimits>,
+}
+
+impl Target {
+    /// A binding with the default settings.
+    pub fn new(kind: TargetKind) -> Self {
+        Self {
+            kind,
+            volume_curve: VolumeCurve::default(),
+            volume_step: DEFAULT_VOLUME_STEP,
+            limits: None,
+        }
+    }
+}
+
+/// A stable description of a binding target.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum TargetKind {
+    /// A stream, matched by its identity if it has one, or otherwise by its name (the endpoint
+    /// name for devices, or the process/display name for sessions).
+    Stream {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        identity: Option<StreamIdentity>,
+    },
+    ActiveWindow,
+    DefaultDevice,
+    DefaultInputDevice,
+    /// Every stream that matches the pattern.
+    Pattern(StreamPattern),
+}
+
+/// Lowers the volume of all bound streams while a priority stream (like voice chat) is audible,
+/// and restores it afterwards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ducking {
+    /// The streams that cause ducking.
+    pub priority: Vec<StreamPattern>,
+    /// The fraction of their volume that the other streams are lowered by.
+    pub amount: f64,
+    /// The peak level above which a priority stream counts as audible.
+    pub threshold: f64,
+    /// How long it takes to lower the volume, in milliseconds.
+    pub attack_ms: u64,
+    /// How long the volume stays lowered after the priority streams go quiet, in milliseconds,
+    /// so it doesn't go up and down between words.
+    pub hold_ms: u64,
+    /// How long it takes to restore the volume, in milliseconds.
+    pub release_ms: u64,
+}
+
+impl Default for Ducking {
+    fn default() -> Self {
+        Self {
+            priority: Vec::new(),
+            amount: 0.5,
+            threshold: 0.02,
+            attack_ms: 100,
+            hold_ms: 500,
+            release_ms: 1000,
+        }
+    }
+}
+
+/// What happens to the saved bindings of a control device when it is disconnected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RemovedDeviceBindings {
+    /// The bindings are restored when the device is connected again.
+    #[default]
+    Keep,
+    /// The bindings are deleted, so the device starts out unbound when it is connected again.
+    Forget,
+}
+
+/// Rules for switching profiles automatically when the focused window changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoProfile {
+    /// How long a window has to stay focused before its profile is switched to, in milliseconds.
+    /// This prevents switching back and forth when quickly passing through windows.
+    pub debounce_ms: u64,
+    /// The profile to switch to when the focused window doesn't match any rule. If this is not
+    /// set, the current profile is kept.
+    pub fallback: Option<String>,
+    /// The first rule that matches the focused window's stream decides the profile.
+    pub rules: Vec<FocusRule>,
+}
+
+impl Default for AutoProfile {
+    fn default() -> Self {
+        Self {
+            debounce_ms: 500,
+            fallback: None,
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FocusRule {
+    pub profile: String,
+    pub pattern: StreamPattern,
+}
+
+/// A named stream pattern.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    pub pattern: StreamPattern,
+}
@@ -6,7 +6,7 @@ use std::{
 
 use smol::channel::{Receiver, Sender, TryRecvError};
 
-use crate::audio::StreamState;
+use crate::audio::{StreamState, VolumeLevel};
 
 type ChannelIndex = usize;
 
@@ -71,10 +71,18 @@ pub enum ControlInput {
 
 #[derive(Debug)]
 pub enum ChannelInput {
+    /// The channel's button went down (`true`) or up (`false`). These are turned into gestures
+    /// by the core, together with `Encoder`.
+    Button(bool),
+    /// The channel's encoder was turned by the given number of steps.
+    Encoder(i32),
     SetVolume(f32),
     StepVolume(i32),
     SetMuted(bool),
     ToggleMuted,
+    /// Mutes the streams of all other channels, or restores them if the channel is already
+    /// soloed.
+    ToggleSolo,
     OpenMenu,
     CloseMenu,
     MenuNext,
@@ -82,16 +90,35 @@ pub enum ChannelInput {
     MenuSelect,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum ControlOutput {
     ChannelOutput(DeviceId, ChannelIndex, ChannelOutput),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ChannelOutput {
     StateChanged(StreamState),
     MenuOpened,
     MenuClosed,
+    /// The highlighted option of the open menu has changed.
+    MenuHighlighted(MenuItem),
+    /// The highest peak level of the channel's streams, whenever one of them reports a new one.
+    /// Only sent if peak meters are enabled in the config.
+    Peak(VolumeLevel),
+    /// Whether the channel is soloed. Only one channel can be soloed at a time.
+    Soloed(bool),
+}
+
+/// The highlighted option of a menu, for control devices that are able to display it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MenuItem {
+    pub text: String,
+    /// The index of the option in the current menu.
+    pub index: usize,
+    /// The number of options in the current menu.
+    pub count: usize,
+    /// How many submenus deep the current menu is; 0 is the top-level menu.
+    pub depth: usize,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -117,6 +144,7 @@ impl DeviceId {
 pub struct DeviceInfo {
     name: String,
     num_channels: usize,
+    serial_number: Option<String>,
 }
 
 impl DeviceInfo {
@@ -124,6 +152,10 @@ impl DeviceInfo {
         &self.name
     }
 
+    pub fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+
     pub fn num_channels(&self) -> usize {
         self.num_channels
     }
@@ -132,17 +164,30 @@ impl DeviceInfo {
 pub struct DeviceInfoBuilder {
     name: String,
     num_channels: usize,
+    serial_number: Option<String>,
 }
 
 impl DeviceInfoBuilder {
     pub fn new(name: String, num_channels: usize) -> Self {
-        Self { name, num_channels }
+        Self {
+            name,
+            num_channels,
+            serial_number: None,
+        }
+    }
+
+    pub fn with_serial_number(self, serial_number: String) -> Self {
+        Self {
+            serial_number: Some(serial_number),
+            ..self
+        }
     }
 
     pub fn build(self) -> DeviceInfo {
         DeviceInfo {
             name: self.name,
             num_channels: self.num_channels,
+            serial_number: self.serial_number,
         }
     }
 }
@@ -1,22 +1,44 @@
 use crate::{
+    action::{self, Action},
     audio::{
-        AudioBackend, AudioControl, AudioEvent, AudioHandle, StreamControl, StreamId, StreamState,
+        Application, AudioBackend, AudioControl, AudioEvent, AudioHandle, Direction, MediaControl,
+        StreamControl, StreamId, StreamIdentity, StreamState, VolumeLevel,
     },
     bigraph::BiGraph,
+    clock::{Clock, SystemClock},
+    config::{
+        ChannelBindings, ChannelKey, ChannelMode, Config, RemovedDeviceBindings, Target,
+        TargetKind, VolumeMode,
+    },
     control::{
         ChannelInput, ChannelOutput, ControlBackend, ControlHandle, ControlInput, ControlOutput,
-        DeviceId,
+        DeviceId, DeviceInfo, MenuItem,
     },
+    gesture::{Gesture, GestureRecognizer},
+    pattern::StreamPattern,
+    volume::{Acceleration, ExternalChange, VolumeCurve, VolumeLimits, DEFAULT_VOLUME_STEP},
 };
 use smol::{
     channel::{Receiver, Sender},
     future::FutureExt,
 };
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
 pub struct Core<A, C> {
     audio_backend: A,
     control_backend: C,
+    config: Config,
+    config_path: Option<PathBuf>,
+    menu_renderer: Option<Box<dyn MenuRenderer>>,
+    clock: Box<dyn Clock>,
+    command_tx: Sender<Command>,
+    command_rx: Receiver<Command>,
 }
 
 impl<A, C> Core<A, C>
@@ -25,18 +47,85 @@ where
     C: ControlBackend,
 {
     pub fn new(audio_backend: A, control_backend: C) -> Self {
+        let (command_tx, command_rx) = smol::channel::unbounded();
         Self {
             audio_backend,
             control_backend,
+            config: Config::default(),
+            config_path: None,
+            menu_renderer: None,
+            clock: Box::new(SystemClock),
+            command_tx,
+            command_rx,
+        }
+    }
+
+    /// Presents the binding menu with the given renderer, in addition to the outputs that are
+    /// sent to the control backend.
+    pub fn with_menu_renderer<R>(self, menu_renderer: R) -> Self
+    where
+        R: MenuRenderer + 'static,
+    {
+        Self {
+            menu_renderer: Some(Box::new(menu_renderer)),
+            ..self
+        }
+    }
+
+    /// Starts with the given configuration, instead of the default one. If a config path is also
+    /// set, the file takes precedence.
+    pub fn with_config(self, config: Config) -> Self {
+        Self { config, ..self }
+    }
+
+    /// Takes the time from the given clock instead of the system's.
+    pub fn with_clock<K>(self, clock: K) -> Self
+    where
+        K: Clock + 'static,
+    {
+        Self {
+            clock: Box::new(clock),
+            ..self
+        }
+    }
+
+    /// Loads the configuration from the given file, and saves any changes (like new bindings)
+    /// back to it.
+    pub fn with_config_path(self, config_path: PathBuf) -> Self {
+        Self {
+            config_path: Some(config_path),
+            ..self
         }
     }
 
+    /// A sender for external commands, which can be used from other threads while the core is
+    /// running.
+    pub fn command_sender(&self) -> Sender<Command> {
+        self.command_tx.clone()
+    }
+
     pub fn run(self) -> Result<(), Box<dyn std::error::Error + 'static>> {
+        smol::block_on(self.run_async())
+    }
+
+    /// Like [`Core::run`], but runs on the caller's executor instead of blocking the thread.
+    pub async fn run_async(self) -> Result<(), Box<dyn std::error::Error + 'static>> {
         let Self {
             audio_backend,
             control_backend,
+            config,
+            config_path,
+            menu_renderer,
+            clock,
+            command_tx: _,
+            command_rx,
         } = self;
 
+        let config = match &config_path {
+            Some(path) => Config::load(path)?,
+            None => config,
+        };
+
         let (audio_event_tx, audio_event_rx) = smol::channel::unbounded();
         let (audio_control_tx, audio_control_rx) = smol::channel::unbounded();
         let audio_handle = AudioHandle::new(audio_event_tx, audio_control_rx);
@@ -55,22 +144,41 @@ where
             result
         };
 
+        let saved_profile = config.active_profile.clone();
         let mut runtime = Runtime {
             audio_event_rx,
             audio_control_tx,
             control_input_rx,
             control_output_tx,
+            command_rx,
+            config,
+            config_path,
+            saved_profile,
+            devices: HashMap::new(),
             streams: HashMap::new(),
             bindings: BiGraph::new(),
             menus: HashMap::new(),
+            menu_renderer,
+            clock,
+            patterns: Vec::new(),
+            group_ratios: HashMap::new(),
             window_focus: None,
+            focused_application: None,
             default_device: None,
+            default_input_device: None,
+            pending_profile: None,
+            last_steps: HashMap::new(),
+            metered: HashSet::new(),
+            ducking: DuckingState::default(),
+            solo: None,
+            gestures: HashMap::new(),
+            held: HashMap::new(),
         };
         let runtime_task = runtime.run();
 
         use smol::future::zip;
         let ((audio_result, control_result), _) =
-            smol::block_on(zip(zip(audio_task, control_task), runtime_task));
+            zip(zip(audio_task, control_task), runtime_task).await;
         audio_result?;
         control_result?;
         Ok(())
@@ -82,11 +190,39 @@ struct Runtime {
     audio_control_tx: Sender<AudioControl>,
     control_input_rx: Receiver<ControlInput>,
     control_output_tx: Sender<ControlOutput>,
+    command_rx: Receiver<Command>,
+    config: Config,
+    config_path: Option<PathBuf>,
+    /// The profile that was last chosen by the user, which is the one that is saved. Automatic
+    /// switches only change the active profile until the next start.
+    saved_profile: String,
+    devices: HashMap<DeviceId, DeviceInfo>,
     streams: HashMap<StreamId, Stream>,
     bindings: BiGraph<ChannelId, Binding>,
     menus: HashMap<ChannelId, Menu>,
+    menu_renderer: Option<Box<dyn MenuRenderer>>,
+    clock: Box<dyn Clock>,
+    patterns: Vec<StreamPattern>,
+    /// The last known relative volumes of each stream in group channels, which are used to
+    /// restore the balance after the whole group has been turned down to zero.
+    group_ratios: HashMap<ChannelId, HashMap<StreamId, VolumeLevel>>,
     window_focus: Option<StreamId>,
+    /// The application of the focused window, which may not have a stream of its own.
+    focused_application: Option<Application>,
     default_device: Option<StreamId>,
+    default_input_device: Option<StreamId>,
+    /// A profile that will be switched to at the given time, unless the focus changes again.
+    pending_profile: Option<(String, Instant)>,
+    /// When each channel last received a step input, for measuring the turning speed.
+    last_steps: HashMap<ChannelId, Instant>,
+    /// Streams that the audio backend is sending peak levels for.
+    metered: HashSet<StreamId>,
+    ducking: DuckingState,
+    solo: Option<Solo>,
+    gestures: HashMap<DeviceId, GestureRecognizer>,
+    /// The mute state that the streams of push-to-talk and push-to-mute channels had before
+    /// their button was pressed.
+    held: HashMap<ChannelId, Vec<(StreamId, bool)>>,
 }
 
 impl Runtime {
@@ -106,8 +242,66 @@ impl Runtime {
                     .ok()
                     .map(Incoming::ControlInput)
             };
-            let incoming = audio_event_task.or(control_input_task).await;
-            log::debug!("incoming {:?}", incoming);
+            let command_task = async {
+                match self.command_rx.recv().await {
+                    Ok(command) => Some(Incoming::Command(command)),
+                    // Commands are optional, keep running if there are no senders.
+                    Err(_) => smol::future::pending().await,
+                }
+            };
+            let profile_task = async {
+                match &self.pending_profile {
+                    Some((_, deadline)) => {
+                        self.clock.sleep_until(*deadline).await;
+                        Some(Incoming::ProfileTimeout)
+                    }
+                    None => smol::future::pending().await,
+                }
+            };
+            let gesture_task = async {
+                let deadline = self
+                    .gestures
+                    .values()
+                    .filter_map(|recognizer| recognizer.next_deadline())
+                    .min();
+                match deadline {
+                    Some(deadline) => {
+                        self.clock.sleep_until(deadline).await;
+                        Some(Incoming::GestureTimeout)
+                    }
+                    None => smol::future::pending().await,
+                }
+            };
+            // The ducking envelope keeps moving while the priority streams are quiet, and
+            // backends don't have to keep sending peaks for that.
+            let ducking_task = async {
+                match self.ducking.last_tick {
+                    Some(last_tick) => {
+                        self.clock.sleep_until(last_tick + DUCKING_TICK).await;
+                        Some(Incoming::DuckingTick)
+                    }
+                    None => smol::future::pending().await,
+                }
+            };
+            let incoming = audio_event_task
+                .or(control_input_task)
+                .or(command_task)
+                .or(profile_task)
+                .or(gesture_task)
+                .or(ducking_task)
+                .await;
+            // Peaks and ducking ticks arrive many times per second, and don't change any
+            // bindings.
+            let is_peak = matches!(
+                incoming,
+                Some(Incoming::AudioEvent(AudioEvent::StreamEvent {
+                    stream_event: crate::audio::StreamEvent::Peak(_),
+                    ..
+                })) | Some(Incoming::DuckingTick)
+            );
+            if !is_peak {
+                log::debug!("incoming {:?}", incoming);
+            }
             match incoming {
                 Some(Incoming::AudioEvent(audio_event)) => match audio_event {
                     AudioEvent::StreamOpened {
@@ -118,12 +312,57 @@ impl Runtime {
                             stream_id,
                             Stream {
                                 name: stream_info.name().to_string(),
+                                identity: stream_info.identity().cloned(),
+                                parent: stream_info.parent(),
+                                direction: stream_info.direction(),
                                 state: stream_info.initial_state(),
+                                peak: 0.0,
                             },
                         );
+                        self.restore_stream(stream_id).await?;
+                        self.update_stream_channels(stream_id).await?;
+                        self.enforce_limits(stream_id).await?;
+                        self.solo_opened_stream(stream_id).await?;
                     }
                     AudioEvent::StreamClosed { stream_id } => {
-                        self.streams.remove(&stream_id);
+                        if let Some(stream) = self.streams.remove(&stream_id) {
+                            for binding in self.matching_patterns(&stream) {
+                                self.update_bound_channels(binding).await?;
+                            }
+                        }
+                        self.ducking.ducked.remove(&stream_id);
+                        self.ducking.touched.remove(&stream_id);
+                        if let Some(solo) = &mut self.solo {
+                            solo.muted.remove(&stream_id);
+                        }
+                        // Backends may reuse IDs, so don't keep pointing at a closed stream.
+                        if self.window_focus == Some(stream_id) {
+                            self.window_focus = None;
+                            self.update_bound_channels(Binding::ActiveWindow).await?;
+                        }
+                        if self.default_device == Some(stream_id) {
+                            self.default_device = None;
+                            self.update_bound_channels(Binding::DefaultDevice).await?;
+                        }
+                        if self.default_input_device == Some(stream_id) {
+                            self.default_input_device = None;
+                            self.update_bound_channels(Binding::DefaultInputDevice)
+                                .await?;
+                        }
+                        if !self.streams.values().any(|stream| self.is_priority(stream)) {
+                            // There won't be any more peaks to release the ducking.
+                            self.end_ducking().await?;
+                        }
+                        // Rebind the affected channels, in case there is another stream that
+                        // matches their saved bindings.
+                        let channels: Vec<ChannelId> = self
+                            .bindings
+                            .neighbors_of_right(Binding::Direct(stream_id))
+                            .collect();
+                        self.bindings.remove_right(Binding::Direct(stream_id));
+                        for channel_id in channels {
+                            self.restore_channel(channel_id).await?;
+                        }
                     }
                     AudioEvent::StreamEvent {
                         stream_id,
@@ -132,83 +371,110 @@ impl Runtime {
                         crate::audio::StreamEvent::StateChanged(state) => {
                             if let Some(stream) = self.streams.get_mut(&stream_id) {
                                 stream.state = state;
-                                self.update_bound_channels(Binding::Direct(stream_id))
-                                    .await?;
-                                if self.window_focus == Some(stream_id) {
-                                    self.update_bound_channels(Binding::ActiveWindow).await?;
+                                self.ducking.check_touched(stream_id, state.volume);
+                                self.update_stream_channels(stream_id).await?;
+                                self.enforce_limits(stream_id).await?;
+                            }
+                        }
+                        crate::audio::StreamEvent::Peak(peak) => {
+                            if let Some(stream) = self.streams.get_mut(&stream_id) {
+                                stream.peak = peak;
+                                if self.config.meter_interval_ms.is_some() {
+                                    self.update_channel_peaks(stream_id).await?;
                                 }
-                                if self.default_device == Some(stream_id) {
-                                    self.update_bound_channels(Binding::DefaultDevice).await?;
+                                if self.is_priority(&self.streams[&stream_id]) {
+                                    self.update_ducking().await?;
                                 }
                             }
                         }
                     },
-                    AudioEvent::WindowFocusChanged { stream_id } => {
+                    AudioEvent::WindowFocusChanged {
+                        stream_id,
+                        application,
+                    } => {
                         self.window_focus = stream_id;
+                        self.focused_application = application;
                         self.update_bound_channels(Binding::ActiveWindow).await?;
+                        self.schedule_focus_profile();
                     }
                     AudioEvent::DefaultDeviceChanged { stream_id } => {
                         self.default_device = stream_id;
                         self.update_bound_channels(Binding::DefaultDevice).await?;
                     }
+                    AudioEvent::DefaultInputDeviceChanged { stream_id } => {
+                        self.default_input_device = stream_id;
+                        self.update_bound_channels(Binding::DefaultInputDevice)
+                            .await?;
+                    }
                 },
                 Some(Incoming::ControlInput(control_input)) => match control_input {
                     ControlInput::DeviceAdded(device_id, device_info) => {
-                        let _ = (device_id, device_info);
+                        self.add_device(device_id, device_info).await?;
                     }
                     ControlInput::DeviceRemoved(device_id) => {
-                        let _ = device_id;
+                        self.remove_device(device_id).await?;
                     }
                     ControlInput::ChannelInput(device_id, channel_index, channel_input) => {
+                        if !self.devices.contains_key(&device_id) {
+                            log::warn!("input from unknown control device {:?}", device_id);
+                            continue;
+                        }
                         let channel_id = ChannelId(device_id, channel_index);
                         match channel_input {
-                            ChannelInput::SetVolume(volume) => {
-                                for binding in self.bindings.neighbors_of_left(channel_id) {
-                                    if let Some(stream_id) = self.binding_stream_id(&binding) {
-                                        self.audio_control_tx
-                                            .send(AudioControl::StreamControl {
-                                                stream_id,
-                                                stream_control: StreamControl::SetVolume(volume),
-                                            })
-                                            .await?;
+                            ChannelInput::Button(pressed) => {
+                                if !self.menus.contains_key(&channel_id) {
+                                    match self.channel_mode(channel_id) {
+                                        ChannelMode::PushToTalk if pressed => {
+                                            self.hold(channel_id, false).await?;
+                                        }
+                                        ChannelMode::PushToMute if pressed => {
+                                            self.hold(channel_id, true).await?;
+                                        }
+                                        _ => {}
                                     }
                                 }
+                                if !pressed {
+                                    self.release(channel_id).await?;
+                                }
+                                // Clicks in the menu always select right away.
+                                let max_clicks = if self.menus.contains_key(&channel_id) {
+                                    1
+                                } else {
+                                    self.config.gestures.max_clicks()
+                                };
+                                let gestures = match self.gestures.get_mut(&device_id) {
+                                    Some(recognizer) => recognizer.button(
+                                        channel_index,
+                                        pressed,
+                                        max_clicks,
+                                        self.clock.now(),
+                                    ),
+                                    None => Vec::new(),
+                                };
+                                self.handle_gestures(device_id, gestures).await?;
+                            }
+                            ChannelInput::Encoder(steps) => {
+                                let gestures = match self.gestures.get_mut(&device_id) {
+                                    Some(recognizer) => recognizer.encoder(channel_index, steps),
+                                    None => Vec::new(),
+                                };
+                                self.handle_gestures(device_id, gestures).await?;
+                            }
+                            ChannelInput::SetVolume(volume) => {
+                                self.set_volume(channel_id, volume).await?;
                             }
                             ChannelInput::StepVolume(steps) => {
-                                for binding in self.bindings.neighbors_of_left(channel_id) {
-                                    if let Some(stream_id) = self.binding_stream_id(&binding) {
-                                        self.audio_control_tx
-                                            .send(AudioControl::StreamControl {
-                                                stream_id,
-                                                stream_control: StreamControl::StepVolume(steps),
-                                            })
-                                            .await?;
-                                    }
-                                }
+                                self.step_volume(channel_id, steps).await?;
                             }
                             ChannelInput::SetMuted(muted) => {
-                                for binding in self.bindings.neighbors_of_left(channel_id) {
-                                    if let Some(stream_id) = self.binding_stream_id(&binding) {
-                                        self.audio_control_tx
-                                            .send(AudioControl::StreamControl {
-                                                stream_id,
-                                                stream_control: StreamControl::SetMuted(muted),
-                                            })
-                                            .await?;
-                                    }
-                                }
+                                self.control_channel(channel_id, StreamControl::SetMuted(muted))
+                                    .await?;
                             }
                             ChannelInput::ToggleMuted => {
-                                for binding in self.bindings.neighbors_of_left(channel_id) {
-                                    if let Some(stream_id) = self.binding_stream_id(&binding) {
-                                        self.audio_control_tx
-                                            .send(AudioControl::StreamControl {
-                                                stream_id,
-                                                stream_control: StreamControl::ToggleMuted,
-                                            })
-                                            .await?;
-                                    }
-                                }
+                                self.toggle_muted(channel_id).await?;
+                            }
+                            ChannelInput::ToggleSolo => {
+                                self.toggle_solo(channel_id).await?;
                             }
                             ChannelInput::OpenMenu => {
                                 self.open_menu(channel_id).await?;
@@ -228,42 +494,460 @@ impl Runtime {
                         }
                     }
                 },
+                Some(Incoming::Command(command)) => match command {
+                    Command::SwitchProfile(name) => {
+                        self.switch_profile(&name).await?;
+                    }
+                },
+                Some(Incoming::ProfileTimeout) => {
+                    if let Some((name, _)) = self.pending_profile.take() {
+                        self.activate_profile(&name).await?;
+                    }
+                }
+                Some(Incoming::GestureTimeout) => {
+                    let now = self.clock.now();
+                    let gestures: Vec<(DeviceId, Vec<(usize, Gesture)>)> = self
+                        .gestures
+                        .iter_mut()
+                        .map(|(&device_id, recognizer)| (device_id, recognizer.poll(now)))
+                        .collect();
+                    for (device_id, gestures) in gestures {
+                        self.handle_gestures(device_id, gestures).await?;
+                    }
+                }
+                Some(Incoming::DuckingTick) => {
+                    self.update_ducking().await?;
+                }
                 None => break,
             }
+            if !is_peak {
+                self.update_meters().await?;
+            }
         }
         Ok(())
     }
 
-    async fn open_menu(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
-        let ChannelId(device_id, channel_index) = channel_id;
+    /// Restores the saved bindings of a newly-connected control device, and sends the current
+    /// state to all of its channels.
+    async fn add_device(
+        &mut self,
+        device_id: DeviceId,
+        device_info: DeviceInfo,
+    ) -> anyhow::Result<()> {
+        log::info!("control device added: {:?}", device_info.name());
+        let num_channels = device_info.num_channels();
+        self.devices.insert(device_id, device_info);
+        self.gestures
+            .insert(device_id, self.config.gestures.recognizer(num_channels));
+        for channel_index in 0..num_channels {
+            self.restore_channel(ChannelId(device_id, channel_index))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Cleans up after a disconnected control device. Its saved bindings are kept or forgotten,
+    /// depending on the config.
+    async fn remove_device(&mut self, device_id: DeviceId) -> anyhow::Result<()> {
+        let device_info = match self.devices.remove(&device_id) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        log::info!("control device removed: {:?}", device_info.name());
+
+        for channel_index in 0..device_info.num_channels() {
+            self.bindings
+                .remove_left(ChannelId(device_id, channel_index));
+        }
+        self.group_ratios
+            .retain(|&ChannelId(channel_device, _), _| channel_device != device_id);
+        self.last_steps
+            .retain(|&ChannelId(channel_device, _), _| channel_device != device_id);
+        self.gestures.remove(&device_id);
+        let held: Vec<ChannelId> = self
+            .held
+            .keys()
+            .copied()
+            .filter(|&ChannelId(channel_device, _)| channel_device == device_id)
+            .collect();
+        for channel_id in held {
+            self.release(channel_id).await?;
+        }
+        if let Some(Solo {
+            channel_id: ChannelId(solo_device, _),
+            ..
+        }) = self.solo
+        {
+            if solo_device == device_id {
+                self.end_solo().await?;
+            }
+        }
+
+        // The device is gone, so there is no point in sending `MenuClosed` to it.
+        let open_menus: Vec<ChannelId> = self
+            .menus
+            .keys()
+            .copied()
+            .filter(|&ChannelId(channel_device, _)| channel_device == device_id)
+            .collect();
+        for channel_id in open_menus {
+            self.menus.remove(&channel_id);
+            if let Some(renderer) = &mut self.menu_renderer {
+                let ChannelId(device_id, channel_index) = channel_id;
+                renderer.close(device_id, channel_index);
+            }
+        }
+
+        match self.config.removed_device_bindings {
+            RemovedDeviceBindings::Keep => {}
+            RemovedDeviceBindings::Forget => {
+                self.config.forget_device(&device_key(&device_info));
+                self.save_config();
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the actions of the gestures that were recognized on the device. Gestures on a
+    /// channel with an open menu navigate the menu instead.
+    async fn handle_gestures(
+        &mut self,
+        device_id: DeviceId,
+        gestures: Vec<(usize, Gesture)>,
+    ) -> anyhow::Result<()> {
+        for (channel_index, gesture) in gestures {
+            log::debug!("gesture {:?} on channel {}", gesture, channel_index);
+            let channel_id = ChannelId(device_id, channel_index);
+            if self.menus.contains_key(&channel_id) {
+                match gesture {
+                    Gesture::Click(_) => self.menu_select(channel_id).await?,
+                    Gesture::LongPress => self.close_menu(channel_id).await?,
+                    Gesture::Turn(steps) | Gesture::PressTurn(steps) => {
+                        self.menu_step(channel_id, steps).await?;
+                    }
+                    Gesture::Chord(_) => {}
+                }
+                continue;
+            }
+            match (self.channel_mode(channel_id), &gesture) {
+                (ChannelMode::OutputSelect, &Gesture::Turn(steps))
+                | (ChannelMode::OutputSelect, &Gesture::PressTurn(steps)) => {
+                    self.open_output_menu(channel_id).await?;
+                    self.menu_step(channel_id, steps).await?;
+                    continue;
+                }
+                // The button is already used for holding.
+                (ChannelMode::PushToTalk, Gesture::Click(_))
+                | (ChannelMode::PushToTalk, Gesture::LongPress)
+                | (ChannelMode::PushToMute, Gesture::Click(_))
+                | (ChannelMode::PushToMute, Gesture::LongPress) => continue,
+                _ => {}
+            }
+            let steps = match gesture {
+                Gesture::Turn(steps) | Gesture::PressTurn(steps) => steps,
+                _ => 1,
+            };
+            let action = self.config.gestures.action(&gesture).clone();
+            self.run_action(channel_id, action, steps).await?;
+        }
+        Ok(())
+    }
+
+    async fn run_action(
+        &mut self,
+        channel_id: ChannelId,
+        action: Action,
+        steps: i32,
+    ) -> anyhow::Result<()> {
+        match action {
+            Action::None => {}
+            Action::ToggleMuted => self.toggle_muted(channel_id).await?,
+            Action::ToggleSolo => self.toggle_solo(channel_id).await?,
+            Action::OpenMenu => self.open_menu(channel_id).await?,
+            Action::StepVolume => self.step_volume(channel_id, steps).await?,
+            Action::SetDefaultDevice(pattern) => {
+                // Devices are the streams without a parent.
+                let device = self
+                    .pattern_stream_ids(&pattern)
+                    .into_iter()
+                    .map(|stream_id| (stream_id, &self.streams[&stream_id]))
+                    .find(|(_, stream)| {
+                        stream.parent.is_none() && stream.direction == Direction::Output
+                    })
+                    .map(|(stream_id, _)| stream_id);
+                match device {
+                    Some(stream_id) => {
+                        self.audio_control_tx
+                            .send(AudioControl::SetDefaultDevice { stream_id })
+                            .await?;
+                    }
+                    None => log::warn!("no device matches {:?}", pattern),
+                }
+            }
+            Action::Media(key) => {
+                self.audio_control_tx.send(AudioControl::Media(key)).await?;
+            }
+            Action::Player(key) => {
+                self.control_players(channel_id, MediaControl::Key(key))
+                    .await?;
+            }
+            Action::Seek(seconds) => {
+                let millis = i64::from(seconds) * i64::from(steps) * 1000;
+                self.control_players(channel_id, MediaControl::Seek(millis))
+                    .await?;
+            }
+            Action::Shell(command) => {
+                log::info!("running {:?}", command);
+                if let Err(e) = action::spawn_shell(&command) {
+                    log::warn!("could not run {:?}: {}", command, e);
+                }
+            }
+            Action::SwitchProfile(name) => self.switch_profile(&name).await?,
+            Action::StepStream(pattern) => {
+                // The streams are stepped like the channel's loudest stream would be.
+                let steps = self.accelerated_steps(channel_id, steps);
+                let curve = self.channel_volume_curve(channel_id);
+                let step_size = self.channel_volume_step(channel_id);
+                let volumes: Vec<(StreamId, VolumeLevel)> = self
+                    .pattern_stream_ids(&pattern)
+                    .into_iter()
+                    .filter_map(|stream_id| {
+                        let stream = self.streams.get(&stream_id)?;
+                        Some((stream_id, curve.step(stream.state.volume, steps, step_size)))
+                    })
+                    .collect();
+                let stream_ids: Vec<StreamId> =
+                    volumes.iter().map(|&(stream_id, _)| stream_id).collect();
+                self.send_stream_volumes(channel_id, volumes).await?;
+                for stream_id in stream_ids {
+                    self.update_stream_channels(stream_id).await?;
+                }
+            }
+            Action::ToggleStreamMuted(pattern) => {
+                for stream_id in self.pattern_stream_ids(&pattern) {
+                    self.audio_control_tx
+                        .send(AudioControl::StreamControl {
+                            stream_id,
+                            stream_control: StreamControl::ToggleMuted,
+                        })
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Controls the media players of the channel's streams.
+    async fn control_players(
+        &mut self,
+        channel_id: ChannelId,
+        media_control: MediaControl,
+    ) -> anyhow::Result<()> {
+        let stream_ids: Vec<StreamId> = self
+            .channel_stream_ids(channel_id)
+            .into_iter()
+            .filter(|stream_id| {
+                self.streams
+                    .get(stream_id)
+                    .is_some_and(|stream| stream.direction == Direction::Output)
+            })
+            .collect();
+        if stream_ids.is_empty() {
+            return Ok(());
+        }
+        self.audio_control_tx
+            .send(AudioControl::MediaControl {
+                stream_ids,
+                media_control,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Switches to the profile at the user's request, and saves it as the profile to start with.
+    async fn switch_profile(&mut self, name: &str) -> anyhow::Result<()> {
+        if self.activate_profile(name).await? {
+            self.saved_profile = name.to_string();
+            self.save_config();
+        }
+        Ok(())
+    }
+
+    /// Switches to the profile without saving it, and returns whether it exists.
+    async fn activate_profile(&mut self, name: &str) -> anyhow::Result<bool> {
+        if !self.config.has_profile(name) {
+            log::warn!("unknown profile {:?}", name);
+            return Ok(false);
+        }
+        log::info!("switching to profile {:?}", name);
+        self.pending_profile = None;
+        self.config.active_profile = name.to_string();
+        self.group_ratios.clear();
+        // The other channels will be bound to different streams.
+        self.end_solo().await?;
+
+        let channels: Vec<ChannelId> = self.channels().collect();
+        for channel_id in channels {
+            if self.menus.contains_key(&channel_id) {
+                self.close_menu(channel_id).await?;
+            }
+            self.restore_channel(channel_id).await?;
+        }
+        Ok(true)
+    }
+
+    /// Schedules a switch to the profile that the auto-profile rules choose for the focused
+    /// window, after the debounce time.
+    fn schedule_focus_profile(&mut self) {
+        let auto_profile = &self.config.auto_profile;
+        if auto_profile.rules.is_empty() {
+            return;
+        }
+        let application = self.focused_application.as_ref();
+        let stream = self
+            .window_focus
+            .and_then(|stream_id| self.streams.get(&stream_id));
+        let profile = auto_profile
+            .rules
+            .iter()
+            .find(|rule| {
+                // The stream can also match, for example when its audio comes from a helper
+                // process with another executable than the window's.
+                application.is_some_and(|application| rule.pattern.matches_application(application))
+                    || stream.is_some_and(|stream| {
+                        rule.pattern.matches(&stream.name, stream.identity.as_ref())
+                    })
+            })
+            .map(|rule| &rule.profile)
+            .or(auto_profile.fallback.as_ref());
+
+        match profile {
+            Some(profile) if *profile != self.config.active_profile => {
+                let already_pending = self
+                    .pending_profile
+                    .as_ref()
+                    .map(|(pending, _)| pending == profile)
+                    .unwrap_or(false);
+                if !already_pending {
+                    let deadline =
+                        self.clock.now() + Duration::from_millis(auto_profile.debounce_ms);
+                    self.pending_profile = Some((profile.clone(), deadline));
+                }
+            }
+            _ => {
+                self.pending_profile = None;
+            }
+        }
+    }
 
+    async fn open_menu(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
         let mut options = Vec::new();
         options.push(MenuOption {
             name: "None".into(),
-            binding: None,
+            action: MenuAction::Bind(None),
         });
         options.push(MenuOption {
             name: "Default Device".into(),
-            binding: Some(Binding::DefaultDevice),
+            action: MenuAction::Bind(Some(Binding::DefaultDevice)),
         });
         options.push(MenuOption {
             name: "Active Window".into(),
-            binding: Some(Binding::ActiveWindow),
+            action: MenuAction::Bind(Some(Binding::ActiveWindow)),
         });
-        options.extend(
-            self.streams
-                .iter()
-                .map(|(stream_id, stream_state)| MenuOption {
-                    name: stream_state.name.clone(),
-                    binding: Some(Binding::Direct(*stream_id)),
-                }),
-        );
-        options[3..].sort_by(|a, b| a.name.cmp(&b.name));
+        let rules = self.config.rules.clone();
+        for rule in &rules {
+            options.push(MenuOption {
+                name: rule.name.clone(),
+                action: MenuAction::Bind(Some(self.pattern_binding(&rule.pattern))),
+            });
+        }
+        options.extend(self.top_level_stream_options(Direction::Output));
+        // Inputs are kept out of the way, since they are bound less often.
+        let input_options = self.top_level_stream_options(Direction::Input);
+        if !input_options.is_empty() {
+            let mut submenu = vec![
+                MenuOption {
+                    name: "Back".into(),
+                    action: MenuAction::Back,
+                },
+                MenuOption {
+                    name: "Default Input Device".into(),
+                    action: MenuAction::Bind(Some(Binding::DefaultInputDevice)),
+                },
+            ];
+            submenu.extend(input_options);
+            options.push(MenuOption {
+                name: "Inputs >".into(),
+                action: MenuAction::Submenu {
+                    title: "Inputs".into(),
+                    options: submenu,
+                },
+            });
+        }
+        for profile in &self.config.profiles {
+            if profile.name != self.config.active_profile {
+                options.push(MenuOption {
+                    name: format!("Profile: {}", profile.name),
+                    action: MenuAction::SwitchProfile(profile.name.clone()),
+                });
+            }
+        }
+        let soloed = self.solo.as_ref().map(|solo| solo.channel_id) == Some(channel_id);
+        if soloed {
+            options.push(MenuOption {
+                name: "End Solo".into(),
+                action: MenuAction::ToggleSolo,
+            });
+        } else if self.bindings.neighbors_of_left(channel_id).next().is_some() {
+            options.push(MenuOption {
+                name: "Solo".into(),
+                action: MenuAction::ToggleSolo,
+            });
+        }
         let menu = Menu {
-            options,
-            current_index: 0,
+            levels: vec![MenuLevel::new(String::new(), options)],
         };
-        menu.print();
+        self.show_menu(channel_id, menu).await
+    }
+
+    /// Opens a menu of the output devices, starting at the default one. Selecting a device makes
+    /// it the default.
+    async fn open_output_menu(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
+        // Devices are the streams without a parent.
+        let mut devices: Vec<(&str, StreamId)> = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| stream.parent.is_none() && stream.direction == Direction::Output)
+            .map(|(&stream_id, stream)| (stream.name.as_str(), stream_id))
+            .collect();
+        if devices.is_empty() {
+            return Ok(());
+        }
+        devices.sort();
+        let current_index = devices
+            .iter()
+            .position(|&(_, stream_id)| Some(stream_id) == self.default_device)
+            .unwrap_or(0);
+        let options = devices
+            .into_iter()
+            .map(|(name, stream_id)| MenuOption {
+                name: name.to_string(),
+                action: MenuAction::SetDefaultDevice(stream_id),
+            })
+            .collect();
+        let mut level = MenuLevel::new(String::new(), options);
+        level.current_index = current_index;
+        self.show_menu(
+            channel_id,
+            Menu {
+                levels: vec![level],
+            },
+        )
+        .await
+    }
+
+    async fn show_menu(&mut self, channel_id: ChannelId, menu: Menu) -> anyhow::Result<()> {
+        let ChannelId(device_id, channel_index) = channel_id;
         self.menus.insert(channel_id, menu);
         self.control_output_tx
             .send(ControlOutput::ChannelOutput(
@@ -272,13 +956,112 @@ impl Runtime {
                 ChannelOutput::MenuOpened,
             ))
             .await?;
+        self.render_menu(channel_id).await?;
         Ok(())
     }
 
+    /// Presents the current state of the channel's menu, both to the control device and to the
+    /// menu renderer.
+    async fn render_menu(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
+        let ChannelId(device_id, channel_index) = channel_id;
+        let menu = match self.menus.get(&channel_id) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let view = menu.view();
+        if let Some(renderer) = &mut self.menu_renderer {
+            renderer.render(device_id, channel_index, &view);
+        }
+        let item = MenuItem {
+            text: view.options[view.current_index].to_string(),
+            index: view.current_index,
+            count: view.options.len(),
+            depth: view.path.len(),
+        };
+        self.control_output_tx
+            .send(ControlOutput::ChannelOutput(
+                device_id,
+                channel_index,
+                ChannelOutput::MenuHighlighted(item),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Options for the streams of the given direction that don't have a parent, sorted by name.
+    fn top_level_stream_options(&self, direction: Direction) -> Vec<MenuOption> {
+        let mut options = self.stream_menu_options(None, direction);
+        // Streams whose parent is unknown would not show up anywhere else.
+        options.extend(
+            self.streams
+                .iter()
+                .filter(|(_, stream)| {
+                    stream.direction == direction
+                        && stream
+                            .parent
+                            .map(|parent| !self.streams.contains_key(&parent))
+                            .unwrap_or(false)
+                })
+                .map(|(&stream_id, stream)| MenuOption {
+                    name: stream.name.clone(),
+                    action: MenuAction::Bind(Some(Binding::Direct(stream_id))),
+                }),
+        );
+        options.sort_by(|a, b| a.name.cmp(&b.name));
+        options
+    }
+
+    /// Options for all streams of the given direction that are children of the given parent.
+    /// Streams that have children of their own get a submenu.
+    fn stream_menu_options(
+        &self,
+        parent: Option<StreamId>,
+        direction: Direction,
+    ) -> Vec<MenuOption> {
+        let mut options: Vec<MenuOption> = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| stream.parent == parent && stream.direction == direction)
+            .map(|(&stream_id, stream)| {
+                let mut children = self.stream_menu_options(Some(stream_id), direction);
+                if children.is_empty() {
+                    return MenuOption {
+                        name: stream.name.clone(),
+                        action: MenuAction::Bind(Some(Binding::Direct(stream_id))),
+                    };
+                }
+                children.sort_by(|a, b| a.name.cmp(&b.name));
+                let mut submenu = vec![
+                    MenuOption {
+                        name: "Back".into(),
+                        action: MenuAction::Back,
+                    },
+                    MenuOption {
+                        name: "Device Volume".into(),
+                        action: MenuAction::Bind(Some(Binding::Direct(stream_id))),
+                    },
+                ];
+                submenu.extend(children);
+                MenuOption {
+                    name: format!("{} >", stream.name),
+                    action: MenuAction::Submenu {
+                        title: stream.name.clone(),
+                        options: submenu,
+                    },
+                }
+            })
+            .collect();
+        options.sort_by(|a, b| a.name.cmp(&b.name));
+        options
+    }
+
     async fn close_menu(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
         let ChannelId(device_id, channel_index) = channel_id;
 
         self.menus.remove(&channel_id);
+        if let Some(renderer) = &mut self.menu_renderer {
+            renderer.close(device_id, channel_index);
+        }
         self.control_output_tx
             .send(ControlOutput::ChannelOutput(
                 device_id,
@@ -291,26 +1074,69 @@ impl Runtime {
 
     async fn menu_next(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
         if let Some(menu) = self.menus.get_mut(&channel_id) {
-            menu.current_index = (menu.current_index + 1).min(menu.options.len() - 1);
-            menu.print();
+            let level = menu.current_mut();
+            level.current_index = (level.current_index + 1).min(level.options.len() - 1);
+            self.render_menu(channel_id).await?;
         }
         Ok(())
     }
 
     async fn menu_previous(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
         if let Some(menu) = self.menus.get_mut(&channel_id) {
-            menu.current_index = menu.current_index.saturating_sub(1);
-            menu.print();
+            let level = menu.current_mut();
+            level.current_index = level.current_index.saturating_sub(1);
+            self.render_menu(channel_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Moves the highlight of the menu by the given number of options.
+    async fn menu_step(&mut self, channel_id: ChannelId, steps: i32) -> anyhow::Result<()> {
+        for _ in 0..steps.abs() {
+            if steps > 0 {
+                self.menu_next(channel_id).await?;
+            } else {
+                self.menu_previous(channel_id).await?;
+            }
         }
         Ok(())
     }
 
     async fn menu_select(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
-        if let Some(menu) = self.menus.get(&channel_id) {
-            let option = menu.options[menu.current_index].clone();
+        if let Some(menu) = self.menus.get_mut(&channel_id) {
+            let level = menu.current();
+            let option = level.options[level.current_index].clone();
             log::info!("selected {:?}", option.name);
-            self.bind(channel_id, option.binding).await?;
-            self.close_menu(channel_id).await?;
+            match option.action {
+                MenuAction::Submenu { title, options } => {
+                    menu.levels.push(MenuLevel::new(title, options));
+                    self.render_menu(channel_id).await?;
+                }
+                MenuAction::Back => {
+                    if menu.levels.len() > 1 {
+                        menu.levels.pop();
+                    }
+                    self.render_menu(channel_id).await?;
+                }
+                MenuAction::Bind(binding) => {
+                    self.bind(channel_id, binding).await?;
+                    self.close_menu(channel_id).await?;
+                }
+                MenuAction::SwitchProfile(name) => {
+                    self.close_menu(channel_id).await?;
+                    self.switch_profile(&name).await?;
+                }
+                MenuAction::ToggleSolo => {
+                    self.close_menu(channel_id).await?;
+                    self.toggle_solo(channel_id).await?;
+                }
+                MenuAction::SetDefaultDevice(stream_id) => {
+                    self.close_menu(channel_id).await?;
+                    self.audio_control_tx
+                        .send(AudioControl::SetDefaultDevice { stream_id })
+                        .await?;
+                }
+            }
         }
         Ok(())
     }
@@ -324,101 +1150,1252 @@ impl Runtime {
         if let Some(binding) = binding {
             self.bindings.add_edge(channel_id, binding);
         }
+        self.save_channel(channel_id);
         self.update_channel(channel_id).await?;
         Ok(())
     }
 
-    fn get_binding_state(&self, binding: &Binding) -> Option<&Stream> {
-        self.binding_stream_id(binding)
-            .and_then(|stream_id| self.streams.get(&stream_id))
-    }
-
-    fn binding_stream_id(&self, binding: &Binding) -> Option<StreamId> {
-        match binding {
-            Binding::Direct(stream_id) => Some(*stream_id),
-            Binding::ActiveWindow => self.window_focus,
-            Binding::DefaultDevice => self.default_device,
+    /// Replaces the bindings of the channel with the ones that were saved for it, skipping any
+    /// targets that are not currently available.
+    async fn restore_channel(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
+        let targets = self
+            .channel_key(channel_id)
+            .map(|key| self.config.channel_targets(&key).to_vec())
+            .unwrap_or_default();
+        self.bindings.remove_left(channel_id);
+        for target in &targets {
+            if let Some(binding) = self.resolve_target(&target.kind) {
+                self.bindings.add_edge(channel_id, binding);
+            }
         }
+        self.update_channel(channel_id).await?;
+        Ok(())
     }
 
-    async fn update_channel(&self, channel_id: ChannelId) -> anyhow::Result<()> {
-        let ChannelId(device_id, channel_index) = channel_id;
-        for binding in self.bindings.neighbors_of_left(channel_id) {
-            let state = self
-                .get_binding_state(&binding)
-                .map(|stream| stream.state)
-                .unwrap_or_default();
-            self.control_output_tx
-                .send(ControlOutput::ChannelOutput(
-                    device_id,
-                    channel_index,
-                    ChannelOutput::StateChanged(state),
-                ))
-                .await?;
+    /// Restores all channels that have a saved binding matching the given stream, but are not
+    /// currently bound to any stream matching that binding.
+    async fn restore_stream(&mut self, stream_id: StreamId) -> anyhow::Result<()> {
+        let stream = match self.streams.get(&stream_id) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let channels: Vec<ChannelId> = self
+            .channels()
+            .filter(|&channel_id| {
+                let targets = match self.channel_key(channel_id) {
+                    Some(key) => self.config.channel_targets(&key),
+                    None => return false,
+                };
+                targets.iter().any(|target| {
+                    stream.matches(&target.kind)
+                        && !self.bindings.neighbors_of_left(channel_id).any(|binding| {
+                            self.get_binding_state(&binding)
+                                .map(|bound| bound.matches(&target.kind))
+                                .unwrap_or(false)
+                        })
+                })
+            })
+            .collect();
+        for channel_id in channels {
+            self.restore_channel(channel_id).await?;
         }
         Ok(())
     }
 
-    async fn update_bound_channels(&self, binding: Binding) -> anyhow::Result<()> {
-        let state = self
-            .get_binding_state(&binding)
-            .map(|stream| stream.state)
-            .unwrap_or_default();
-        for ChannelId(device_id, channel_index) in self.bindings.neighbors_of_right(binding) {
-            self.control_output_tx
-                .send(ControlOutput::ChannelOutput(
-                    device_id,
-                    channel_index,
-                    ChannelOutput::StateChanged(state),
-                ))
-                .await?;
-        }
-        Ok(())
+    /// Saves the current bindings of the channel to the config.
+    fn save_channel(&mut self, channel_id: ChannelId) {
+        let key = match self.channel_key(channel_id) {
+            Some(x) => x,
+            None => return,
+        };
+        let saved = self.config.channel_targets(&key);
+        let targets = self
+            .bindings
+            .neighbors_of_left(channel_id)
+            .filter_map(|binding| {
+                let kind = self.binding_target(&binding)?;
+                // Binding the same target again keeps its settings.
+                let target = saved
+                    .iter()
+                    .find(|target| match &binding {
+                        Binding::Direct(stream_id) => self
+                            .streams
+                            .get(stream_id)
+                            .is_some_and(|stream| stream.matches(&target.kind)),
+                        _ => target.kind == kind,
+                    })
+                    .cloned()
+                    .unwrap_or_else(|| Target::new(kind));
+                Some(target)
+            })
+            .collect();
+        self.config.set_channel_targets(key, targets);
+        self.save_config();
     }
-}
 
-#[derive(Debug)]
-enum Incoming {
-    AudioEvent(AudioEvent),
-    ControlInput(ControlInput),
-}
+    fn save_config(&mut self) {
+        let path = match &self.config_path {
+            Some(x) => x,
+            None => return,
+        };
+        // The bindings of an automatically chosen profile are saved, but not the choice itself.
+        let active_profile =
+            std::mem::replace(&mut self.config.active_profile, self.saved_profile.clone());
+        if let Err(e) = self.config.save(path) {
+            log::warn!("could not save config: {}", e);
+        }
+        self.config.active_profile = active_profile;
+    }
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-struct ChannelId(DeviceId, usize);
+    /// All channels of all currently-connected devices.
+    fn channels(&self) -> impl Iterator<Item = ChannelId> + '_ {
+        self.devices.iter().flat_map(|(&device_id, device_info)| {
+            (0..device_info.num_channels()).map(move |index| ChannelId(device_id, index))
+        })
+    }
 
-struct Menu {
-    options: Vec<MenuOption>,
-    current_index: usize,
-}
+    fn channel_key(&self, channel_id: ChannelId) -> Option<ChannelKey> {
+        let ChannelId(device_id, channel_index) = channel_id;
+        let device_info = self.devices.get(&device_id)?;
+        Some(ChannelKey {
+            device: device_key(device_info),
+            channel: channel_index,
+        })
+    }
 
-impl Menu {
-    fn print(&self) {
-        for (i, option) in self.options.iter().enumerate() {
-            if i == self.current_index {
-                print!("> ");
-            } else {
-                print!("  ");
+    fn binding_target(&self, binding: &Binding) -> Option<TargetKind> {
+        match binding {
+            Binding::Direct(stream_id) => {
+                self.streams
+                    .get(stream_id)
+                    .map(|stream| TargetKind::Stream {
+                        name: stream.name.clone(),
+                        identity: stream.identity.clone(),
+                    })
+            }
+            Binding::ActiveWindow => Some(TargetKind::ActiveWindow),
+            Binding::DefaultDevice => Some(TargetKind::DefaultDevice),
+            Binding::DefaultInputDevice => Some(TargetKind::DefaultInputDevice),
+            Binding::Pattern(PatternId(index)) => {
+                Some(TargetKind::Pattern(self.patterns[*index].clone()))
             }
-            println!("{}", option.name);
         }
-        println!();
     }
-}
-
-#[derive(Clone)]
-struct MenuOption {
-    name: String,
-    binding: Option<Binding>,
-}
+
+    fn resolve_target(&mut self, target: &TargetKind) -> Option<Binding> {
+        match target {
+            TargetKind::Stream { .. } => self
+                .streams
+                .iter()
+                .filter(|(_, stream)| stream.matches(target))
+                .map(|(&stream_id, _)| stream_id)
+                .min()
+                .map(Binding::Direct),
+            TargetKind::ActiveWindow => Some(Binding::ActiveWindow),
+            TargetKind::DefaultDevice => Some(Binding::DefaultDevice),
+            TargetKind::DefaultInputDevice => Some(Binding::DefaultInputDevice),
+            TargetKind::Pattern(pattern) => Some(self.pattern_binding(pattern)),
+        }
+    }
+
+    fn get_binding_state(&self, binding: &Binding) -> Option<&Stream> {
+        self.binding_stream_ids(binding)
+            .into_iter()
+            .find_map(|stream_id| self.streams.get(&stream_id))
+    }
+
+    fn binding_stream_ids(&self, binding: &Binding) -> Vec<StreamId> {
+        match binding {
+            Binding::Direct(stream_id) => vec![*stream_id],
+            Binding::ActiveWindow => self.window_focus.into_iter().collect(),
+            Binding::DefaultDevice => self.default_device.into_iter().collect(),
+            Binding::DefaultInputDevice => self.default_input_device.into_iter().collect(),
+            Binding::Pattern(PatternId(index)) => self.pattern_stream_ids(&self.patterns[*index]),
+        }
+    }
+
+    /// All streams that match the pattern, in a stable order.
+    fn pattern_stream_ids(&self, pattern: &StreamPattern) -> Vec<StreamId> {
+        let mut stream_ids: Vec<StreamId> = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| pattern.matches(&stream.name, stream.identity.as_ref()))
+            .map(|(&stream_id, _)| stream_id)
+            .collect();
+        stream_ids.sort();
+        stream_ids
+    }
+
+    /// All streams that are bound to the channel, without duplicates.
+    fn channel_stream_ids(&self, channel_id: ChannelId) -> Vec<StreamId> {
+        let mut stream_ids: Vec<StreamId> = self
+            .bindings
+            .neighbors_of_left(channel_id)
+            .flat_map(|binding| self.binding_stream_ids(&binding))
+            .collect();
+        stream_ids.sort();
+        stream_ids.dedup();
+        stream_ids
+    }
+
+    /// All channels that the stream is bound to, either directly or indirectly.
+    fn stream_channels(&self, stream_id: StreamId) -> Vec<ChannelId> {
+        self.channels()
+            .filter(|&channel_id| self.channel_stream_ids(channel_id).contains(&stream_id))
+            .collect()
+    }
+
+    /// Subscribes to the peak levels of all streams that are bound to a channel, and
+    /// unsubscribes from the ones that aren't anymore.
+    async fn update_meters(&mut self) -> anyhow::Result<()> {
+        let mut bound: HashSet<StreamId> = HashSet::new();
+        if self.config.meter_interval_ms.is_some() {
+            bound.extend(
+                self.channels()
+                    .flat_map(|channel_id| self.channel_stream_ids(channel_id))
+                    .filter(|stream_id| self.streams.contains_key(stream_id)),
+            );
+        }
+        // Ducking needs to know when the priority streams are audible, bound or not.
+        bound.extend(
+            self.streams
+                .iter()
+                .filter(|(_, stream)| self.is_priority(stream))
+                .map(|(&stream_id, _)| stream_id),
+        );
+        let interval = self
+            .config
+            .meter_interval_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_METER_INTERVAL);
+
+        for &stream_id in bound.difference(&self.metered) {
+            self.audio_control_tx
+                .send(AudioControl::MeterSubscribe {
+                    stream_id,
+                    interval,
+                })
+                .await?;
+        }
+        for &stream_id in self.metered.difference(&bound) {
+            // Closed streams don't need to be unsubscribed.
+            if let Some(stream) = self.streams.get_mut(&stream_id) {
+                stream.peak = 0.0;
+                self.audio_control_tx
+                    .send(AudioControl::MeterUnsubscribe { stream_id })
+                    .await?;
+            }
+        }
+        self.metered = bound;
+        Ok(())
+    }
+
+    /// Whether the stream causes ducking when it is audible.
+    fn is_priority(&self, stream: &Stream) -> bool {
+        match &self.config.ducking {
+            Some(ducking) => ducking
+                .priority
+                .iter()
+                .any(|pattern| pattern.matches(&stream.name, stream.identity.as_ref())),
+            None => false,
+        }
+    }
+
+    /// Moves the ducking gain towards its target, and applies it to the bound streams. This is
+    /// called for every peak of a priority stream, and on a timer while ducking, until the
+    /// release has finished.
+    async fn update_ducking(&mut self) -> anyhow::Result<()> {
+        let ducking = match &self.config.ducking {
+            Some(x) => x.clone(),
+            None => return Ok(()),
+        };
+        let now = self.clock.now();
+        let audible = self
+            .streams
+            .values()
+            .any(|stream| self.is_priority(stream) && f64::from(stream.peak) >= ducking.threshold);
+        if audible {
+            self.ducking.last_audible = Some(now);
+        }
+        let holding = audible
+            || self
+                .ducking
+                .last_audible
+                .map(|last| now.duration_since(last) < Duration::from_millis(ducking.hold_ms))
+                .unwrap_or(false);
+
+        let elapsed = self
+            .ducking
+            .last_tick
+            .replace(now)
+            .map(|last| now.duration_since(last))
+            .unwrap_or_default();
+        let ducked_gain = (1.0 - ducking.amount).clamp(0.0, 1.0);
+        let gain = f64::from(self.ducking.gain);
+        let gain = if holding {
+            approach(
+                gain,
+                ducked_gain,
+                ducking.amount,
+                elapsed,
+                ducking.attack_ms,
+            )
+        } else {
+            approach(gain, 1.0, ducking.amount, elapsed, ducking.release_ms)
+        };
+        self.ducking.gain = gain as VolumeLevel;
+        if !holding && self.ducking.gain >= 1.0 {
+            return self.end_ducking().await;
+        }
+
+        // Streams that were bound since the ducking started are ducked too.
+        let priority_ancestors: HashSet<StreamId> = self
+            .streams
+            .values()
+            .filter(|stream| self.is_priority(stream))
+            .flat_map(|stream| self.ancestors(stream))
+            .collect();
+        let new_streams: Vec<StreamId> = self
+            .channels()
+            .flat_map(|channel_id| self.channel_stream_ids(channel_id))
+            .filter(|stream_id| {
+                !self.ducking.ducked.contains_key(stream_id)
+                    && !self.ducking.touched.contains(stream_id)
+                    && !priority_ancestors.contains(stream_id)
+            })
+            .collect();
+        for stream_id in new_streams {
+            if let Some(stream) = self.streams.get(&stream_id) {
+                if !self.is_priority(stream) && stream.direction == Direction::Output {
+                    let volume = stream.state.volume;
+                    self.ducking.ducked.insert(
+                        stream_id,
+                        Ducked {
+                            original: volume,
+                            current: volume,
+                        },
+                    );
+                }
+            }
+        }
+        self.apply_ducking().await
+    }
+
+    /// Restores the volume of all ducked streams.
+    async fn end_ducking(&mut self) -> anyhow::Result<()> {
+        self.ducking.gain = 1.0;
+        self.apply_ducking().await?;
+        self.ducking = DuckingState::default();
+        Ok(())
+    }
+
+    async fn apply_ducking(&mut self) -> anyhow::Result<()> {
+        let gain = self.ducking.gain;
+        let mut stream_ids: Vec<StreamId> = self.ducking.ducked.keys().copied().collect();
+        stream_ids.sort();
+        for stream_id in stream_ids {
+            let ducked = self.ducking.ducked.get_mut(&stream_id).unwrap();
+            let volume = ducked.original * gain;
+            if volume != ducked.current {
+                ducked.current = volume;
+                self.audio_control_tx
+                    .send(AudioControl::StreamControl {
+                        stream_id,
+                        stream_control: StreamControl::SetVolume(volume),
+                    })
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The parent of the stream, its parent, and so on.
+    fn ancestors(&self, stream: &Stream) -> Vec<StreamId> {
+        let mut ancestors = Vec::new();
+        let mut parent = stream.parent;
+        while let Some(stream_id) = parent {
+            if ancestors.contains(&stream_id) {
+                break;
+            }
+            ancestors.push(stream_id);
+            parent = self
+                .streams
+                .get(&stream_id)
+                .and_then(|stream| stream.parent);
+        }
+        ancestors
+    }
+
+    /// Sends the highest peak level of each channel that the stream is bound to.
+    async fn update_channel_peaks(&self, stream_id: StreamId) -> anyhow::Result<()> {
+        for channel_id in self.stream_channels(stream_id) {
+            let ChannelId(device_id, channel_index) = channel_id;
+            let peak = self
+                .channel_stream_ids(channel_id)
+                .iter()
+                .filter_map(|stream_id| self.streams.get(stream_id))
+                .map(|stream| stream.peak)
+                .fold(0.0, f32::max);
+            self.control_output_tx
+                .send(ControlOutput::ChannelOutput(
+                    device_id,
+                    channel_index,
+                    ChannelOutput::Peak(peak),
+                ))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Sends the control to every stream that is bound to the channel.
+    async fn control_channel(
+        &self,
+        channel_id: ChannelId,
+        stream_control: StreamControl,
+    ) -> anyhow::Result<()> {
+        for stream_id in self.channel_stream_ids(channel_id) {
+            self.audio_control_tx
+                .send(AudioControl::StreamControl {
+                    stream_id,
+                    stream_control,
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// The saved bindings and settings of the channel, if there are any.
+    fn channel_bindings(&self, channel_id: ChannelId) -> Option<&ChannelBindings> {
+        let key = self.channel_key(channel_id)?;
+        self.config.channel_bindings(&key)
+    }
+
+    fn channel_mode(&self, channel_id: ChannelId) -> ChannelMode {
+        self.channel_bindings(channel_id)
+            .map(|bindings| bindings.mode)
+            .unwrap_or_default()
+    }
+
+    fn volume_mode(&self, channel_id: ChannelId) -> VolumeMode {
+        self.channel_bindings(channel_id)
+            .map(|bindings| bindings.volume_mode)
+            .unwrap_or_default()
+    }
+
+    /// The saved target that binds the stream to the channel, which holds the settings of that
+    /// binding.
+    fn stream_target(&self, channel_id: ChannelId, stream_id: StreamId) -> Option<&Target> {
+        let stream = self.streams.get(&stream_id)?;
+        let bindings = self.channel_bindings(channel_id)?;
+        bindings.targets.iter().find(|target| match &target.kind {
+            TargetKind::Stream { .. } => stream.matches(&target.kind),
+            TargetKind::ActiveWindow => self.window_focus == Some(stream_id),
+            TargetKind::DefaultDevice => self.default_device == Some(stream_id),
+            TargetKind::DefaultInputDevice => self.default_input_device == Some(stream_id),
+            TargetKind::Pattern(pattern) => pattern.matches(&stream.name, stream.identity.as_ref()),
+        })
+    }
+
+    /// The loudest stream that is bound to the channel, which is the one whose volume the
+    /// channel shows.
+    fn loudest_stream(&self, channel_id: ChannelId) -> Option<StreamId> {
+        self.channel_stream_ids(channel_id)
+            .into_iter()
+            .filter_map(|stream_id| {
+                let stream = self.streams.get(&stream_id)?;
+                Some((stream_id, stream.state.volume))
+            })
+            .fold(None, |loudest, (stream_id, volume)| match loudest {
+                Some((_, loudest_volume)) if loudest_volume >= volume => loudest,
+                _ => Some((stream_id, volume)),
+            })
+            .map(|(stream_id, _)| stream_id)
+    }
+
+    fn volume_curve(&self, channel_id: ChannelId, stream_id: StreamId) -> VolumeCurve {
+        self.stream_target(channel_id, stream_id)
+            .map(|target| target.volume_curve.clone())
+            .unwrap_or_default()
+    }
+
+    fn volume_step(&self, channel_id: ChannelId, stream_id: StreamId) -> f64 {
+        self.stream_target(channel_id, stream_id)
+            .map(|target| target.volume_step)
+            .unwrap_or(DEFAULT_VOLUME_STEP)
+    }
+
+    /// The saved target that stands for the channel as a whole: the binding of its loudest
+    /// stream, or its first binding if none of its streams are open.
+    fn channel_target(&self, channel_id: ChannelId) -> Option<&Target> {
+        match self.loudest_stream(channel_id) {
+            Some(stream_id) => self.stream_target(channel_id, stream_id),
+            None => self.channel_bindings(channel_id)?.targets.first(),
+        }
+    }
+
+    fn channel_volume_curve(&self, channel_id: ChannelId) -> VolumeCurve {
+        self.channel_target(channel_id)
+            .map(|target| target.volume_curve.clone())
+            .unwrap_or_default()
+    }
+
+    fn channel_volume_step(&self, channel_id: ChannelId) -> f64 {
+        self.channel_target(channel_id)
+            .map(|target| target.volume_step)
+            .unwrap_or(DEFAULT_VOLUME_STEP)
+    }
+
+    /// The limits of the stream's binding to the channel. Streams that are not bound to the
+    /// channel, like the ones that a `StepStream` action controls, get the limits of the channel
+    /// as a whole.
+    fn volume_limits(&self, channel_id: ChannelId, stream_id: StreamId) -> Option<VolumeLimits> {
+        let target = if self.channel_stream_ids(channel_id).contains(&stream_id) {
+            self.stream_target(channel_id, stream_id)
+        } else {
+            self.channel_target(channel_id)
+        };
+        target.and_then(|target| target.limits.clone())
+    }
+
+    /// The acceleration of the channel's own settings, or otherwise of its device.
+    fn acceleration(&self, channel_id: ChannelId) -> Acceleration {
+        if let Some(acceleration) = self
+            .channel_bindings(channel_id)
+            .and_then(|bindings| bindings.acceleration.clone())
+        {
+            return acceleration;
+        }
+        let ChannelId(device_id, _) = channel_id;
+        self.devices
+            .get(&device_id)
+            .and_then(|device_info| self.config.device_settings(&device_key(device_info)))
+            .map(|settings| settings.acceleration.clone())
+            .unwrap_or_default()
+    }
+
+    /// Moves the channel's streams to the given position on the volume curves of their
+    /// bindings.
+    async fn set_volume(&mut self, channel_id: ChannelId, position: f32) -> anyhow::Result<()> {
+        match self.volume_mode(channel_id) {
+            VolumeMode::Independent => {
+                let volumes = self
+                    .channel_stream_ids(channel_id)
+                    .into_iter()
+                    .map(|stream_id| {
+                        let volume = self.volume_curve(channel_id, stream_id).volume(position);
+                        (stream_id, volume)
+                    })
+                    .collect();
+                self.set_stream_volumes(channel_id, volumes).await
+            }
+            VolumeMode::Group => {
+                let volume = self.channel_volume_curve(channel_id).volume(position);
+                self.set_group_volume(channel_id, volume).await
+            }
+        }
+    }
+
+    /// Moves the channel's streams along the volume curves of their bindings by the given
+    /// number of steps, which are accelerated depending on how fast they arrive.
+    async fn step_volume(&mut self, channel_id: ChannelId, steps: i32) -> anyhow::Result<()> {
+        let steps = self.accelerated_steps(channel_id, steps);
+        match self.volume_mode(channel_id) {
+            VolumeMode::Independent => {
+                // Every stream is stepped from its own volume.
+                let volumes = self
+                    .channel_stream_ids(channel_id)
+                    .into_iter()
+                    .filter_map(|stream_id| {
+                        let stream = self.streams.get(&stream_id)?;
+                        let volume = self.volume_curve(channel_id, stream_id).step(
+                            stream.state.volume,
+                            steps,
+                            self.volume_step(channel_id, stream_id),
+                        );
+                        Some((stream_id, volume))
+                    })
+                    .collect();
+                self.set_stream_volumes(channel_id, volumes).await
+            }
+            VolumeMode::Group => {
+                let volume = self.channel_volume_curve(channel_id).step(
+                    self.channel_state(channel_id).volume,
+                    steps,
+                    self.channel_volume_step(channel_id),
+                );
+                self.set_group_volume(channel_id, volume).await
+            }
+        }
+    }
+
+    /// The number of steps after the channel's acceleration, which depends on the time since
+    /// its last step input.
+    fn accelerated_steps(&mut self, channel_id: ChannelId, steps: i32) -> f64 {
+        let now = self.clock.now();
+        let elapsed = self
+            .last_steps
+            .insert(channel_id, now)
+            .map(|last| now.duration_since(last));
+        let factor = self.acceleration(channel_id).factor(steps, elapsed);
+        f64::from(steps) * factor
+    }
+
+    async fn toggle_muted(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
+        match self.volume_mode(channel_id) {
+            VolumeMode::Independent => {
+                self.control_channel(channel_id, StreamControl::ToggleMuted)
+                    .await
+            }
+            VolumeMode::Group => {
+                // Toggling each stream separately could leave them out of sync.
+                let muted = !self.channel_state(channel_id).muted;
+                self.control_channel(channel_id, StreamControl::SetMuted(muted))
+                    .await
+            }
+        }
+    }
+
+    /// Mutes or unmutes the channel's streams while its button is held down, and remembers how
+    /// they were before.
+    async fn hold(&mut self, channel_id: ChannelId, muted: bool) -> anyhow::Result<()> {
+        let mut previous = Vec::new();
+        for stream_id in self.channel_stream_ids(channel_id) {
+            if let Some(stream) = self.streams.get(&stream_id) {
+                previous.push((stream_id, stream.state.muted));
+                self.audio_control_tx
+                    .send(AudioControl::StreamControl {
+                        stream_id,
+                        stream_control: StreamControl::SetMuted(muted),
+                    })
+                    .await?;
+            }
+        }
+        self.held.insert(channel_id, previous);
+        Ok(())
+    }
+
+    /// Restores the streams of a channel whose button was held down, if it was.
+    async fn release(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
+        let previous = match self.held.remove(&channel_id) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        for (stream_id, muted) in previous {
+            if self.streams.contains_key(&stream_id) {
+                self.audio_control_tx
+                    .send(AudioControl::StreamControl {
+                        stream_id,
+                        stream_control: StreamControl::SetMuted(muted),
+                    })
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Solos the channel, or ends the solo if it is already soloed. Soloing a channel while
+    /// another one is soloed moves the solo over to it.
+    async fn toggle_solo(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
+        let soloed = self.solo.as_ref().map(|solo| solo.channel_id);
+        self.end_solo().await?;
+        if soloed != Some(channel_id) {
+            self.start_solo(channel_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Mutes every stream that is bound to another channel, and remembers whether it was muted
+    /// before.
+    async fn start_solo(&mut self, channel_id: ChannelId) -> anyhow::Result<()> {
+        let soloed = self.channel_stream_ids(channel_id);
+        // Muting a device would also silence the sessions on it.
+        let keep: HashSet<StreamId> = soloed
+            .iter()
+            .filter_map(|stream_id| self.streams.get(stream_id))
+            .flat_map(|stream| self.ancestors(stream))
+            .chain(soloed.iter().copied())
+            .collect();
+        let mut others: Vec<StreamId> = self
+            .channels()
+            .filter(|&other| other != channel_id)
+            .flat_map(|other| self.channel_stream_ids(other))
+            .filter(|stream_id| !keep.contains(stream_id))
+            .collect();
+        others.sort();
+        others.dedup();
+
+        let mut muted = HashMap::new();
+        for stream_id in others {
+            let stream = match self.streams.get(&stream_id) {
+                Some(x) => x,
+                None => continue,
+            };
+            // Solo is about what you hear, so microphones are left alone.
+            if stream.direction == Direction::Input {
+                continue;
+            }
+            muted.insert(stream_id, stream.state.muted);
+            if !stream.state.muted {
+                self.audio_control_tx
+                    .send(AudioControl::StreamControl {
+                        stream_id,
+                        stream_control: StreamControl::SetMuted(true),
+                    })
+                    .await?;
+            }
+        }
+        log::info!("soloing {} stream(s)", soloed.len());
+        self.solo = Some(Solo { channel_id, muted });
+
+        let ChannelId(device_id, channel_index) = channel_id;
+        self.control_output_tx
+            .send(ControlOutput::ChannelOutput(
+                device_id,
+                channel_index,
+                ChannelOutput::Soloed(true),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Mutes a stream that was opened during a solo, if it is bound to another channel than the
+    /// soloed one.
+    async fn solo_opened_stream(&mut self, stream_id: StreamId) -> anyhow::Result<()> {
+        let channel_id = match &self.solo {
+            Some(solo) => solo.channel_id,
+            None => return Ok(()),
+        };
+        let stream = match self.streams.get(&stream_id) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        if stream.direction == Direction::Input {
+            return Ok(());
+        }
+        let muted = stream.state.muted;
+        let channels = self.stream_channels(stream_id);
+        if channels.is_empty() || channels.contains(&channel_id) {
+            return Ok(());
+        }
+        // A device that the soloed sessions play on has to stay unmuted.
+        let is_ancestor = self
+            .channel_stream_ids(channel_id)
+            .iter()
+            .filter_map(|soloed| self.streams.get(soloed))
+            .any(|soloed| self.ancestors(soloed).contains(&stream_id));
+        if is_ancestor {
+            return Ok(());
+        }
+
+        if let Some(solo) = &mut self.solo {
+            solo.muted.insert(stream_id, muted);
+        }
+        if !muted {
+            self.audio_control_tx
+                .send(AudioControl::StreamControl {
+                    stream_id,
+                    stream_control: StreamControl::SetMuted(true),
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Restores the mute state of the streams that were muted by the solo, if there is one.
+    async fn end_solo(&mut self) -> anyhow::Result<()> {
+        let solo = match self.solo.take() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let mut stream_ids: Vec<StreamId> = solo.muted.keys().copied().collect();
+        stream_ids.sort();
+        for stream_id in stream_ids {
+            self.audio_control_tx
+                .send(AudioControl::StreamControl {
+                    stream_id,
+                    stream_control: StreamControl::SetMuted(solo.muted[&stream_id]),
+                })
+                .await?;
+        }
+
+        let ChannelId(device_id, channel_index) = solo.channel_id;
+        if self.devices.contains_key(&device_id) {
+            self.control_output_tx
+                .send(ControlOutput::ChannelOutput(
+                    device_id,
+                    channel_index,
+                    ChannelOutput::Soloed(false),
+                ))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Sets the volume of the loudest stream in the channel, and scales the others so that
+    /// their volumes stay relative to it.
+    async fn set_group_volume(
+        &mut self,
+        channel_id: ChannelId,
+        volume: VolumeLevel,
+    ) -> anyhow::Result<()> {
+        let volume = volume.clamp(0.0, 1.0);
+        let stream_ids = self.channel_stream_ids(channel_id);
+        let max_volume = stream_ids
+            .iter()
+            .filter_map(|stream_id| self.streams.get(stream_id))
+            .map(|stream| stream.state.volume)
+            .fold(0.0, f32::max);
+
+        let ratios = self.group_ratios.entry(channel_id).or_default();
+        if max_volume > 0.0 {
+            ratios.clear();
+            for stream_id in &stream_ids {
+                if let Some(stream) = self.streams.get(stream_id) {
+                    ratios.insert(*stream_id, stream.state.volume / max_volume);
+                }
+            }
+        }
+
+        let volumes = stream_ids
+            .into_iter()
+            .map(|stream_id| {
+                let ratio = ratios.get(&stream_id).copied().unwrap_or(1.0);
+                (stream_id, volume * ratio)
+            })
+            .collect();
+        self.set_stream_volumes(channel_id, volumes).await
+    }
+
+    /// Sends the volumes to the streams, within the limits of their bindings, and updates the
+    /// channel.
+    async fn set_stream_volumes(
+        &mut self,
+        channel_id: ChannelId,
+        volumes: Vec<(StreamId, VolumeLevel)>,
+    ) -> anyhow::Result<()> {
+        self.send_stream_volumes(channel_id, volumes).await?;
+        self.update_channel(channel_id).await?;
+        Ok(())
+    }
+
+    /// Sends the volumes to the streams, within the limits of their bindings.
+    async fn send_stream_volumes(
+        &mut self,
+        channel_id: ChannelId,
+        volumes: Vec<(StreamId, VolumeLevel)>,
+    ) -> anyhow::Result<()> {
+        for (stream_id, volume) in volumes {
+            let volume = match self.volume_limits(channel_id, stream_id) {
+                Some(limits) => limits.clamp(volume),
+                None => volume,
+            };
+            self.ducking.touch(stream_id);
+            self.audio_control_tx
+                .send(AudioControl::StreamControl {
+                    stream_id,
+                    stream_control: StreamControl::SetVolume(volume),
+                })
+                .await?;
+            // Update the state right away, so steps that arrive before the backend reports
+            // the change are applied to the new volume.
+            if let Some(stream) = self.streams.get_mut(&stream_id) {
+                stream.state.volume = volume;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the volume of a stream back within the limits of its bindings to channels,
+    /// after it was changed by someone else. Channels that only display such changes are
+    /// skipped, and so are ducked streams, which may be lowered below the limits until the
+    /// ducking ends.
+    async fn enforce_limits(&mut self, stream_id: StreamId) -> anyhow::Result<()> {
+        if self.ducking.ducked.contains_key(&stream_id) {
+            return Ok(());
+        }
+        let volume = match self.streams.get(&stream_id) {
+            Some(stream) => stream.state.volume,
+            None => return Ok(()),
+        };
+        let clamped = self
+            .stream_channels(stream_id)
+            .into_iter()
+            .filter_map(|channel_id| self.volume_limits(channel_id, stream_id))
+            .filter(|limits| limits.external == ExternalChange::Clamp)
+            .fold(volume, |volume, limits| limits.clamp(volume));
+        if clamped != volume {
+            log::info!("clamping volume of {:?} to {}", stream_id, clamped);
+            self.audio_control_tx
+                .send(AudioControl::StreamControl {
+                    stream_id,
+                    stream_control: StreamControl::SetVolume(clamped),
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Gets the binding for the given pattern, registering it if it's new.
+    fn pattern_binding(&mut self, pattern: &StreamPattern) -> Binding {
+        let index = match self.patterns.iter().position(|p| p == pattern) {
+            Some(index) => index,
+            None => {
+                self.patterns.push(pattern.clone());
+                self.patterns.len() - 1
+            }
+        };
+        Binding::Pattern(PatternId(index))
+    }
+
+    /// All pattern bindings that match the given stream.
+    fn matching_patterns(&self, stream: &Stream) -> Vec<Binding> {
+        self.patterns
+            .iter()
+            .enumerate()
+            .filter(|(_, pattern)| pattern.matches(&stream.name, stream.identity.as_ref()))
+            .map(|(index, _)| Binding::Pattern(PatternId(index)))
+            .collect()
+    }
+
+    /// The combined state of all streams bound to the channel: the volume of the loudest
+    /// stream, and muted only if all of them are muted.
+    fn channel_state(&self, channel_id: ChannelId) -> StreamState {
+        let states: Vec<StreamState> = self
+            .channel_stream_ids(channel_id)
+            .iter()
+            .filter_map(|stream_id| self.streams.get(stream_id))
+            .map(|stream| stream.state)
+            .collect();
+        if states.is_empty() {
+            return StreamState::default();
+        }
+        StreamState {
+            volume: states.iter().map(|state| state.volume).fold(0.0, f32::max),
+            muted: states.iter().all(|state| state.muted),
+        }
+    }
+
+    /// Sends the state of the channel to its control device, with the volume as a position on
+    /// the channel's volume curve.
+    async fn update_channel(&self, channel_id: ChannelId) -> anyhow::Result<()> {
+        let ChannelId(device_id, channel_index) = channel_id;
+        let mut state = self.channel_state(channel_id);
+        state.volume = self.channel_volume_curve(channel_id).position(state.volume);
+        self.control_output_tx
+            .send(ControlOutput::ChannelOutput(
+                device_id,
+                channel_index,
+                ChannelOutput::StateChanged(state),
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Updates all channels that are bound to the stream, either directly or indirectly.
+    async fn update_stream_channels(&self, stream_id: StreamId) -> anyhow::Result<()> {
+        self.update_bound_channels(Binding::Direct(stream_id))
+            .await?;
+        if self.window_focus == Some(stream_id) {
+            self.update_bound_channels(Binding::ActiveWindow).await?;
+        }
+        if self.default_device == Some(stream_id) {
+            self.update_bound_channels(Binding::DefaultDevice).await?;
+        }
+        if self.default_input_device == Some(stream_id) {
+            self.update_bound_channels(Binding::DefaultInputDevice)
+                .await?;
+        }
+        if let Some(stream) = self.streams.get(&stream_id) {
+            for binding in self.matching_patterns(stream) {
+                self.update_bound_channels(binding).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn update_bound_channels(&self, binding: Binding) -> anyhow::Result<()> {
+        for channel_id in self.bindings.neighbors_of_right(binding) {
+            self.update_channel(channel_id).await?;
+        }
+        Ok(())
+    }
+}
+
+/// The key that identifies a control device in the config: its serial number, or its name if it
+/// doesn't have one.
+fn device_key(device_info: &DeviceInfo) -> String {
+    device_info
+        .serial_number()
+        .unwrap_or_else(|| device_info.name())
+        .to_string()
+}
+
+/// How often peak levels are requested if they are only needed for ducking.
+const DEFAULT_METER_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often the ducking gain is updated while ducking, in addition to every peak.
+const DUCKING_TICK: Duration = Duration::from_millis(20);
+
+/// Moves `value` towards `target`, at a rate where `range` is covered in `duration_ms`.
+fn approach(value: f64, target: f64, range: f64, elapsed: Duration, duration_ms: u64) -> f64 {
+    if duration_ms == 0 {
+        return target;
+    }
+    let delta = range * elapsed.as_secs_f64() * 1000.0 / duration_ms as f64;
+    if value < target {
+        (value + delta).min(target)
+    } else {
+        (value - delta).max(target)
+    }
+}
+
+/// Commands that can be sent to the core from outside of the audio and control backends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    SwitchProfile(String),
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::SwitchProfile(name) => write!(f, "profile {}", name),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (command, argument) = s.trim().split_once(' ').unwrap_or((s.trim(), ""));
+        match (command, argument.trim()) {
+            ("profile", name) if !name.is_empty() => Ok(Self::SwitchProfile(name.to_string())),
+            _ => Err(anyhow::anyhow!("invalid command {:?}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Incoming {
+    AudioEvent(AudioEvent),
+    ControlInput(ControlInput),
+    Command(Command),
+    ProfileTimeout,
+    GestureTimeout,
+    DuckingTick,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct ChannelId(DeviceId, usize);
+
+struct Menu {
+    /// The top-level menu, followed by the submenus that are currently entered.
+    levels: Vec<MenuLevel>,
+}
+
+impl Menu {
+    fn current(&self) -> &MenuLevel {
+        self.levels.last().expect("menu has no levels")
+    }
+
+    fn current_mut(&mut self) -> &mut MenuLevel {
+        self.levels.last_mut().expect("menu has no levels")
+    }
+
+    fn view(&self) -> MenuView<'_> {
+        let level = self.current();
+        MenuView {
+            path: self.levels[1..]
+                .iter()
+                .map(|level| level.title.as_str())
+                .collect(),
+            options: level
+                .options
+                .iter()
+                .map(|option| option.name.as_str())
+                .collect(),
+            current_index: level.current_index,
+        }
+    }
+}
+
+struct DuckingState {
+    /// The fraction of their original volume that ducked streams currently get.
+    gain: VolumeLevel,
+    last_tick: Option<Instant>,
+    last_audible: Option<Instant>,
+    ducked: HashMap<StreamId, Ducked>,
+    /// Streams whose volume was changed by the user while ducking, which are left alone until
+    /// the ducking ends.
+    touched: HashSet<StreamId>,
+}
+
+impl Default for DuckingState {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            last_tick: None,
+            last_audible: None,
+            ducked: HashMap::new(),
+            touched: HashSet::new(),
+        }
+    }
+}
+
+impl DuckingState {
+    /// Stops ducking the stream, because the user changed its volume.
+    fn touch(&mut self, stream_id: StreamId) {
+        if self.ducked.remove(&stream_id).is_some() {
+            self.touched.insert(stream_id);
+        }
+    }
+
+    /// Stops ducking the stream if its volume was changed by someone else.
+    fn check_touched(&mut self, stream_id: StreamId, volume: VolumeLevel) {
+        if let Some(ducked) = self.ducked.get(&stream_id) {
+            if (ducked.current - volume).abs() > 0.005 {
+                self.touch(stream_id);
+            }
+        }
+    }
+}
+
+/// The soloed channel, and the mute state of the other streams from before it was soloed.
+struct Solo {
+    channel_id: ChannelId,
+    muted: HashMap<StreamId, bool>,
+}
+
+struct Ducked {
+    /// The volume before ducking, which is restored afterwards.
+    original: VolumeLevel,
+    /// The volume that the stream was last set to.
+    current: VolumeLevel,
+}
+
+/// A snapshot of an open menu, as it should be presented to the user.
+pub struct MenuView<'a> {
+    /// The titles of the submenus that are entered, from the outermost to the innermost.
+    pub path: Vec<&'a str>,
+    /// The options of the innermost menu.
+    pub options: Vec<&'a str>,
+    /// The index of the highlighted option.
+    pub current_index: usize,
+}
+
+/// Presents the binding menu to the user, for example in a terminal or a desktop notification.
+///
+/// Control devices are also notified about the highlighted option through
+/// [`ChannelOutput::MenuHighlighted`], so this is only needed for presenting the menu somewhere
+/// else.
+pub trait MenuRenderer {
+    /// Called whenever the menu is opened or changed.
+    fn render(&mut self, device_id: DeviceId, channel_index: usize, menu: &MenuView);
+
+    /// Called when the menu is closed.
+    fn close(&mut self, device_id: DeviceId, channel_index: usize) {
+        let _ = (device_id, channel_index);
+    }
+}
+
+/// Prints the menu to stdout.
+pub struct StdoutMenuRenderer;
+
+impl MenuRenderer for StdoutMenuRenderer {
+    fn render(&mut self, device_id: DeviceId, channel_index: usize, menu: &MenuView) {
+        let _ = (device_id, channel_index);
+        if !menu.path.is_empty() {
+            println!("[{}]", menu.path.join(" "));
+        }
+        for (i, option) in menu.options.iter().enumerate() {
+            if i == menu.current_index {
+                print!("> ");
+            } else {
+                print!("  ");
+            }
+            println!("{}", option);
+        }
+        println!();
+    }
+}
+
+struct MenuLevel {
+    title: String,
+    options: Vec<MenuOption>,
+    current_index: usize,
+}
+
+impl MenuLevel {
+    fn new(title: String, options: Vec<MenuOption>) -> Self {
+        Self {
+            title,
+            options,
+            current_index: 0,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct MenuOption {
+    name: String,
+    action: MenuAction,
+}
+
+#[derive(Clone)]
+enum MenuAction {
+    Bind(Option<Binding>),
+    SwitchProfile(String),
+    ToggleSolo,
+    SetDefaultDevice(StreamId),
+    /// Enters a submenu with the given options.
+    Submenu {
+        title: String,
+        options: Vec<MenuOption>,
+    },
+    /// Leaves the current submenu.
+    Back,
+}
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 enum Binding {
     Direct(StreamId),
     ActiveWindow,
     DefaultDevice,
+    DefaultInputDevice,
+    /// Every stream that matches a pattern.
+    Pattern(PatternId),
 }
 
+/// Index of a pattern in `Runtime::patterns`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct PatternId(usize);
+
 struct Stream {
     name: String,
+    identity: Option<StreamIdentity>,
+    parent: Option<StreamId>,
+    direction: Direction,
     state: StreamState,
+    /// The last reported peak level, if the stream is metered.
+    peak: VolumeLevel,
+}
+
+impl Stream {
+    /// Whether this stream is the one described by a saved stream target.
+    fn matches(&self, target: &TargetKind) -> bool {
+        match target {
+            TargetKind::Stream {
+                identity: Some(identity),
+                ..
+            } => self
+                .identity
+                .as_ref()
+                .is_some_and(|own| own.matches(identity)),
+            TargetKind::Stream {
+                identity: None,
+                name,
+            } => self.name == *name,
+            _ => false,
+        }
+    }
 }
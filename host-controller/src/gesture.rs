@@ -0,0 +1,393 @@
+//! Recognizes gestures from the raw button and encoder inputs of a control device, and maps
+//! them to actions.
+//!
+//! Control backends only report when a button goes down or up, and how far an encoder was
+//! turned (see [`ChannelInput::Button`] and [`ChannelInput::Encoder`]). The core runs a
+//! [`GestureRecognizer`] for each device, so every backend gets the same gestures, and they are
+//! configured in one place.
+//!
+//! [`ChannelInput::Button`]: crate::control::ChannelInput::Button
+//! [`ChannelInput::Encoder`]: crate::control::ChannelInput::Encoder
+
+use crate::action::Action;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// A gesture on a single channel, or on several channels at once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Gesture {
+    /// The button was pressed and released this many times in quick succession.
+    Click(u32),
+    /// The button was held down without turning. This is sent while it is still held.
+    LongPress,
+    /// The encoder was turned while the button was up.
+    Turn(i32),
+    /// The encoder was turned while the button was held down.
+    PressTurn(i32),
+    /// The buttons of these channels were held down together. This is sent for the first of the
+    /// channels, once all of them are released.
+    Chord(Vec<usize>),
+}
+
+/// The actions of each gesture, and their timing.
+///
+/// While a channel's menu is open, its gestures always navigate the menu: turns move the
+/// highlight, clicks select, and long presses close it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Gestures {
+    /// How long to wait for another click, in milliseconds. Single clicks are delayed by this
+    /// much if double or triple clicks are used.
+    pub click_ms: u64,
+    /// How long a button has to be held down for a long press, in milliseconds.
+    pub long_press_ms: u64,
+    pub click: Action,
+    pub double_click: Action,
+    pub triple_click: Action,
+    pub long_press: Action,
+    pub turn: Action,
+    pub press_turn: Action,
+    pub chords: Vec<Chord>,
+}
+
+impl Default for Gestures {
+    fn default() -> Self {
+        Self {
+            click_ms: 300,
+            long_press_ms: 500,
+            click: Action::ToggleMuted,
+            double_click: Action::None,
+            triple_click: Action::None,
+            long_press: Action::OpenMenu,
+            turn: Action::StepVolume,
+            press_turn: Action::StepVolume,
+            chords: Vec::new(),
+        }
+    }
+}
+
+impl Gestures {
+    /// The action of the gesture. Chords that aren't configured don't do anything.
+    pub fn action(&self, gesture: &Gesture) -> &Action {
+        match gesture {
+            Gesture::Click(1) => &self.click,
+            Gesture::Click(2) => &self.double_click,
+            Gesture::Click(_) => &self.triple_click,
+            Gesture::LongPress => &self.long_press,
+            Gesture::Turn(_) => &self.turn,
+            Gesture::PressTurn(_) => &self.press_turn,
+            Gesture::Chord(channels) => self
+                .chords
+                .iter()
+                .find(|chord| chord.matches(channels))
+                .map(|chord| &chord.action)
+                .unwrap_or(&Action::None),
+        }
+    }
+
+    /// The most clicks that have an action, which is how many the recognizer waits for.
+    pub fn max_clicks(&self) -> u32 {
+        if self.triple_click != Action::None {
+            3
+        } else if self.double_click != Action::None {
+            2
+        } else {
+            1
+        }
+    }
+
+    pub fn recognizer(&self, num_channels: usize) -> GestureRecognizer {
+        GestureRecognizer::new(
+            num_channels,
+            Duration::from_millis(self.click_ms),
+            Duration::from_millis(self.long_press_ms),
+        )
+    }
+}
+
+/// An action for pressing the buttons of several channels of the same device together.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chord {
+    pub channels: Vec<usize>,
+    pub action: Action,
+}
+
+impl Chord {
+    fn matches(&self, channels: &[usize]) -> bool {
+        let mut own = self.channels.clone();
+        own.sort_unstable();
+        own.dedup();
+        own == channels
+    }
+}
+
+/// Turns the button and encoder inputs of a single device into gestures.
+///
+/// The recognizer doesn't keep time by itself: every input takes the current time, and
+/// [`GestureRecognizer::poll`] has to be called at [`GestureRecognizer::next_deadline`] to
+/// finish pending clicks and long presses.
+pub struct GestureRecognizer {
+    channels: Vec<ChannelState>,
+    click_interval: Duration,
+    long_press: Duration,
+}
+
+#[derive(Default, Clone)]
+struct ChannelState {
+    pressed_at: Option<Instant>,
+    /// Whether the current press has already been used for something other than a click.
+    consumed: bool,
+    /// Other channels that were held down together with this one, including itself.
+    chord: Vec<usize>,
+    clicks: u32,
+    click_deadline: Option<Instant>,
+}
+
+impl GestureRecognizer {
+    pub fn new(num_channels: usize, click_interval: Duration, long_press: Duration) -> Self {
+        Self {
+            channels: vec![ChannelState::default(); num_channels],
+            click_interval,
+            long_press,
+        }
+    }
+
+    /// Handles a button going down or up. `max_clicks` is how many clicks to wait for before
+    /// reporting them.
+    pub fn button(
+        &mut self,
+        channel: usize,
+        pressed: bool,
+        max_clicks: u32,
+        now: Instant,
+    ) -> Vec<(usize, Gesture)> {
+        let mut gestures = Vec::new();
+        if channel >= self.channels.len() {
+            return gestures;
+        }
+        let held: Vec<usize> = (0..self.channels.len())
+            .filter(|&index| index != channel && self.channels[index].pressed_at.is_some())
+            .collect();
+
+        if pressed {
+            if self.channels[channel].pressed_at.is_some() {
+                return gestures;
+            }
+            // Clicks on other channels can't continue anymore.
+            for other in 0..self.channels.len() {
+                if other != channel {
+                    gestures.extend(self.finish_clicks(other));
+                }
+            }
+            let state = &mut self.channels[channel];
+            state.pressed_at = Some(now);
+            state.consumed = false;
+            state.chord.clear();
+            if !held.is_empty() {
+                let mut chord: Vec<usize> = held
+                    .iter()
+                    .flat_map(|&index| self.channels[index].chord.clone())
+                    .chain(held.iter().copied())
+                    .chain(Some(channel))
+                    .collect();
+                chord.sort_unstable();
+                chord.dedup();
+                for &index in &chord {
+                    let state = &mut self.channels[index];
+                    state.chord = chord.clone();
+                    state.consumed = true;
+                    state.clicks = 0;
+                    state.click_deadline = None;
+                }
+            }
+        } else {
+            let state = &mut self.channels[channel];
+            if state.pressed_at.take().is_none() {
+                return gestures;
+            }
+            if !state.chord.is_empty() {
+                // The chord is done when the last of its buttons is released.
+                let chord = state.chord.clone();
+                if chord
+                    .iter()
+                    .all(|&index| self.channels[index].pressed_at.is_none())
+                {
+                    for &index in &chord {
+                        self.channels[index].chord.clear();
+                    }
+                    gestures.push((chord[0], Gesture::Chord(chord)));
+                }
+            } else if !state.consumed {
+                state.clicks += 1;
+                if state.clicks >= max_clicks {
+                    gestures.extend(self.finish_clicks(channel));
+                } else {
+                    state.click_deadline = Some(now + self.click_interval);
+                }
+            }
+        }
+        gestures
+    }
+
+    /// Handles an encoder being turned by the given number of steps.
+    pub fn encoder(&mut self, channel: usize, steps: i32) -> Vec<(usize, Gesture)> {
+        let mut gestures = Vec::new();
+        if channel >= self.channels.len() || steps == 0 {
+            return gestures;
+        }
+        gestures.extend(self.finish_clicks(channel));
+        let state = &mut self.channels[channel];
+        if state.pressed_at.is_some() {
+            state.consumed = true;
+            gestures.push((channel, Gesture::PressTurn(steps)));
+        } else {
+            gestures.push((channel, Gesture::Turn(steps)));
+        }
+        gestures
+    }
+
+    /// Reports the clicks and long presses whose time has come.
+    pub fn poll(&mut self, now: Instant) -> Vec<(usize, Gesture)> {
+        let mut gestures = Vec::new();
+        for channel in 0..self.channels.len() {
+            let state = &mut self.channels[channel];
+            if let Some(pressed_at) = state.pressed_at {
+                if !state.consumed && now >= pressed_at + self.long_press {
+                    state.consumed = true;
+                    // The clicks before it are a separate gesture.
+                    gestures.extend(self.finish_clicks(channel));
+                    gestures.push((channel, Gesture::LongPress));
+                }
+            } else if state
+                .click_deadline
+                .map(|deadline| now >= deadline)
+                .unwrap_or(false)
+            {
+                gestures.extend(self.finish_clicks(channel));
+            }
+        }
+        gestures
+    }
+
+    /// When [`GestureRecognizer::poll`] has to be called next, if there is anything pending.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.channels
+            .iter()
+            .filter_map(|state| match state.pressed_at {
+                Some(pressed_at) if !state.consumed => Some(pressed_at + self.long_press),
+                Some(_) => None,
+                None => state.click_deadline,
+            })
+            .min()
+    }
+
+    fn finish_clicks(&mut self, channel: usize) -> Option<(usize, Gesture)> {
+        let state = &mut self.channels[channel];
+        state.click_deadline = None;
+        match std::mem::take(&mut state.clicks) {
+            0 => None,
+            clicks => Some((channel, Gesture::Click(clicks))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recognizer() -> GestureRecognizer {
+        Gestures::default().recognizer(3)
+    }
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn clicks() {
+        let mut recognizer = recognizer();
+        let start = Instant::now();
+        // Single clicks are reported right away if nothing else is waited for.
+        recognizer.button(0, true, 1, start);
+        assert_eq!(
+            recognizer.button(0, false, 1, ms(start, 50)),
+            vec![(0, Gesture::Click(1))]
+        );
+
+        recognizer.button(0, true, 3, ms(start, 100));
+        assert!(recognizer.button(0, false, 3, ms(start, 150)).is_empty());
+        recognizer.button(0, true, 3, ms(start, 200));
+        assert!(recognizer.button(0, false, 3, ms(start, 250)).is_empty());
+        assert_eq!(recognizer.next_deadline(), Some(ms(start, 550)));
+        assert!(recognizer.poll(ms(start, 500)).is_empty());
+        assert_eq!(
+            recognizer.poll(ms(start, 550)),
+            vec![(0, Gesture::Click(2))]
+        );
+        assert_eq!(recognizer.next_deadline(), None);
+    }
+
+    #[test]
+    fn long_press_and_turns() {
+        let mut recognizer = recognizer();
+        let start = Instant::now();
+        recognizer.button(1, true, 1, start);
+        assert_eq!(recognizer.next_deadline(), Some(ms(start, 500)));
+        assert_eq!(
+            recognizer.poll(ms(start, 500)),
+            vec![(1, Gesture::LongPress)]
+        );
+        assert!(recognizer.button(1, false, 1, ms(start, 600)).is_empty());
+
+        assert_eq!(recognizer.encoder(1, -2), vec![(1, Gesture::Turn(-2))]);
+        recognizer.button(1, true, 1, ms(start, 700));
+        assert_eq!(recognizer.encoder(1, 1), vec![(1, Gesture::PressTurn(1))]);
+        // Turning cancels both the click and the long press.
+        assert_eq!(recognizer.next_deadline(), None);
+        assert!(recognizer.button(1, false, 1, ms(start, 800)).is_empty());
+    }
+
+    #[test]
+    fn chords() {
+        let mut recognizer = recognizer();
+        let start = Instant::now();
+        recognizer.button(2, true, 1, start);
+        recognizer.button(0, true, 1, ms(start, 20));
+        assert_eq!(recognizer.next_deadline(), None);
+        assert!(recognizer.button(2, false, 1, ms(start, 100)).is_empty());
+        assert_eq!(
+            recognizer.button(0, false, 1, ms(start, 120)),
+            vec![(0, Gesture::Chord(vec![0, 2]))]
+        );
+
+        let gestures = Gestures {
+            chords: vec![Chord {
+                channels: vec![2, 0],
+                action: Action::ToggleSolo,
+            }],
+            ..Gestures::default()
+        };
+        assert_eq!(
+            gestures.action(&Gesture::Chord(vec![0, 2])),
+            &Action::ToggleSolo
+        );
+        assert_eq!(gestures.action(&Gesture::Chord(vec![0, 1])), &Action::None);
+    }
+
+    #[test]
+    fn config_keys() {
+        let gestures: Gestures = toml::from_str(
+            r#"
+            click_ms = 250
+            long_press_ms = 800
+            double_click = "toggle-solo"
+            press_turn = "none"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(gestures.click_ms, 250);
+        assert_eq!(gestures.long_press_ms, 800);
+        assert_eq!(gestures.double_click, Action::ToggleSolo);
+        assert_eq!(gestures.press_turn, Action::None);
+    }
+}
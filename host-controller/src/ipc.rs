@@ -0,0 +1,159 @@
+use crate::core::Command;
+use smol::{
+    channel::Sender,
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    stream::StreamExt,
+    Timer,
+};
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+/// Where the command server listens. On Unix, this is a socket file that only the current user
+/// can connect to.
+#[cfg(unix)]
+pub type Address = std::path::PathBuf;
+/// Where the command server listens. Windows has no socket files, so this is a TCP address on the
+/// loopback interface, which every local user can connect to.
+#[cfg(not(unix))]
+pub type Address = String;
+
+/// The socket in the user's runtime directory, or in the cache directory if there is none.
+#[cfg(unix)]
+pub fn default_address() -> Option<Address> {
+    let dir =
+        dirs::runtime_dir().or_else(|| dirs::cache_dir().map(|dir| dir.join("windowmaster")))?;
+    Some(dir.join("windowmaster.sock"))
+}
+
+#[cfg(not(unix))]
+pub fn default_address() -> Option<Address> {
+    Some("127.0.0.1:47863".into())
+}
+
+/// Listens for connections from other processes, and forwards the commands they send (one per
+/// line) to the core. Each connection is handled on its own, so an idle client doesn't hold up
+/// the others.
+pub async fn serve(address: &Address, command_tx: Sender<Command>) -> io::Result<()> {
+    let listener = bind(address).await?;
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let command_tx = command_tx.clone();
+                smol::spawn(async move {
+                    if let Err(e) = handle(stream, &command_tx).await {
+                        log::warn!("command connection failed: {}", e);
+                    }
+                })
+                .detach();
+            }
+            Err(e) => {
+                // For example, too many open files. Waiting gives the other connections a chance
+                // to close.
+                log::warn!("could not accept a command connection: {}", e);
+                Timer::after(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn bind(address: &Address) -> io::Result<smol::net::unix::UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(dir) = address.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // A socket that nobody listens on is left over from an instance that didn't shut down
+    // cleanly.
+    if address.exists() && std::os::unix::net::UnixStream::connect(address).is_err() {
+        std::fs::remove_file(address)?;
+    }
+    let listener = smol::net::unix::UnixListener::bind(address)?;
+    std::fs::set_permissions(address, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+async fn bind(address: &Address) -> io::Result<smol::net::TcpListener> {
+    smol::net::TcpListener::bind(address.as_str()).await
+}
+
+async fn handle(stream: impl AsyncRead + Unpin, command_tx: &Sender<Command>) -> io::Result<()> {
+    let mut lines = BufReader::new(stream).lines();
+    let mut first = true;
+    while let Some(line) = lines.next().await {
+        let line = line?;
+        // Web pages can send requests to local ports, and the body of such a request could
+        // contain a command.
+        if first && looks_like_http(&line) {
+            log::warn!("ignoring an HTTP request to the command server");
+            break;
+        }
+        first = false;
+        match line.parse() {
+            Ok(command) => {
+                log::debug!("received command {:?}", command);
+                if command_tx.send(command).await.is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                log::warn!("{}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether the line is the request line of an HTTP request, like `POST / HTTP/1.1`.
+fn looks_like_http(line: &str) -> bool {
+    line.trim_end()
+        .rsplit(' ')
+        .next()
+        .unwrap_or_default()
+        .starts_with("HTTP/")
+}
+
+/// Sends a command to a running instance.
+pub fn send(address: &Address, command: &Command) -> io::Result<()> {
+    #[cfg(unix)]
+    let mut stream = std::os::unix::net::UnixStream::connect(address)?;
+    #[cfg(not(unix))]
+    let mut stream = std::net::TcpStream::connect(address.as_str())?;
+    writeln!(stream, "{}", command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn http_requests() {
+        assert!(looks_like_http("POST / HTTP/1.1"));
+        assert!(looks_like_http("GET /favicon.ico HTTP/1.0\r"));
+        assert!(!looks_like_http("profile Gaming"));
+        assert!(!looks_like_http("profile HTTP"));
+        assert!(!looks_like_http(""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn idle_clients_dont_block_commands() {
+        let address =
+            std::env::temp_dir().join(format!("windowmaster-{}.sock", std::process::id()));
+        let (command_tx, command_rx) = smol::channel::unbounded();
+        let server_address = address.clone();
+        std::thread::spawn(move || smol::block_on(serve(&server_address, command_tx)));
+        while std::os::unix::net::UnixStream::connect(&address).is_err() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let _idle = std::os::unix::net::UnixStream::connect(&address).unwrap();
+        let command = Command::SwitchProfile("Gaming".into());
+        send(&address, &command).unwrap();
+        assert_eq!(smol::block_on(command_rx.recv()), Ok(command));
+        std::fs::remove_file(&address).ok();
+    }
+}
@@ -1,9 +1,20 @@
+pub mod action;
 pub mod audio;
 pub mod backend;
 pub mod bigraph;
+pub mod clock;
+pub mod config;
 pub mod control;
 pub mod core;
+pub mod gesture;
+pub mod ipc;
+pub mod pattern;
+pub mod process;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod volume;
 
+#[cfg(windows)]
 mod bindings {
     windows::include_bindings!();
 }
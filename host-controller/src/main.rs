@@ -1,14 +1,48 @@
+#[cfg(target_os = "linux")]
+use windowmaster::backend::pulse::PulseAudioBackend as PlatformAudioBackend;
+#[cfg(windows)]
+use windowmaster::backend::windows::WindowsAudioBackend as PlatformAudioBackend;
 use windowmaster::{
-    backend::{hidapi::HidApiControlBackend, windows::WindowsAudioBackend},
-    core::Core,
+    backend::hidapi::HidApiControlBackend,
+    config::Config,
+    core::{Command, Core, StdoutMenuRenderer},
+    ipc,
 };
 
 pub fn main() -> anyhow::Result<()> {
     env_logger::init();
 
-    Core::new(WindowsAudioBackend::new(), HidApiControlBackend)
-        .run()
-        .map_err(|e| anyhow::anyhow!("{}", e))?;
+    // `windowmaster profile <name>` switches the profile of the running instance.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let ipc_address = ipc::default_address();
+    if !args.is_empty() {
+        let command: Command = args.join(" ").parse()?;
+        let ipc_address =
+            ipc_address.ok_or_else(|| anyhow::anyhow!("no runtime directory available"))?;
+        ipc::send(&ipc_address, &command)?;
+        return Ok(());
+    }
+
+    let config_path =
+        Config::default_path().ok_or_else(|| anyhow::anyhow!("no config directory available"))?;
+
+    let core = Core::new(PlatformAudioBackend::new(), HidApiControlBackend)
+        .with_config_path(config_path)
+        .with_menu_renderer(StdoutMenuRenderer);
+
+    match ipc_address {
+        Some(ipc_address) => {
+            let command_tx = core.command_sender();
+            std::thread::spawn(move || {
+                if let Err(e) = smol::block_on(ipc::serve(&ipc_address, command_tx)) {
+                    log::warn!("command server exited: {}", e);
+                }
+            });
+        }
+        None => log::warn!("no runtime directory available, commands are disabled"),
+    }
+
+    core.run().map_err(|e| anyhow::anyhow!("{}", e))?;
 
     Ok(())
 }
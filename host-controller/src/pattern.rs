@@ -0,0 +1,164 @@
+use crate::audio::{Application, StreamIdentity};
+use serde::{Deserialize, Serialize};
+
+/// A rule that matches any number of streams by their name and/or owning process.
+///
+/// Every field that is set has to match for the pattern to match. Fields are case-insensitive
+/// globs, where `*` matches any sequence of characters and `?` matches any single character.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StreamPattern {
+    /// Matched against the display name of the stream, or the name of a window's application.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Matched against either the full path or the file name of the session's executable.
+    /// Streams without an executable (like devices) never match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub executable: Option<String>,
+}
+
+impl StreamPattern {
+    pub fn matches(&self, name: &str, identity: Option<&StreamIdentity>) -> bool {
+        let executable = match identity {
+            Some(StreamIdentity::Session {
+                executable: Some(executable),
+                ..
+            }) => Some(executable.as_str()),
+            _ => None,
+        };
+        self.matches_fields(Some(name), executable)
+    }
+
+    /// Whether the application of a window matches, with its name in place of a stream's.
+    pub fn matches_application(&self, application: &Application) -> bool {
+        self.matches_fields(
+            application.name.as_deref(),
+            application.executable.as_deref(),
+        )
+    }
+
+    fn matches_fields(&self, name: Option<&str>, executable: Option<&str>) -> bool {
+        let name_matches = match (&self.name, name) {
+            (Some(pattern), Some(name)) => glob_match(pattern, name),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        let executable_matches = match (&self.executable, executable) {
+            (Some(pattern), Some(executable)) => {
+                glob_match(pattern, executable) || glob_match(pattern, file_name(executable))
+            }
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        name_matches && executable_matches
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit(&['/', '\\'][..]).next().unwrap_or(path)
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    let mut p = 0;
+    let mut t = 0;
+    // Position of the last `*` seen, and the text position it is currently matched up to.
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    // Let the last `*` consume one more character and try again.
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(executable: &str) -> StreamIdentity {
+        StreamIdentity::Session {
+            endpoint_id: "endpoint".into(),
+            session_identifier: None,
+            executable: Some(executable.into()),
+        }
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("firefox", "Firefox"));
+        assert!(glob_match("firefox*", "firefox"));
+        assert!(glob_match("firefox*", "Firefox Nightly"));
+        assert!(glob_match("*fox", "firefox"));
+        assert!(glob_match("f?re*x", "firefox"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(!glob_match("firefox*", "chrome"));
+        assert!(!glob_match("f?x", "fx"));
+        assert!(!glob_match("*a*b", "xxaxxbxx"));
+    }
+
+    #[test]
+    fn name() {
+        let pattern = StreamPattern {
+            name: Some("firefox*".into()),
+            executable: None,
+        };
+        assert!(pattern.matches("Firefox", None));
+        assert!(pattern.matches("Firefox", Some(&session("C:\\chrome.exe"))));
+        assert!(!pattern.matches("Chrome", None));
+    }
+
+    #[test]
+    fn executable() {
+        let pattern = StreamPattern {
+            name: None,
+            executable: Some("game.exe".into()),
+        };
+        assert!(pattern.matches("Game", Some(&session("C:\\Games\\Game.exe"))));
+        assert!(pattern.matches("Launcher", Some(&session("/usr/bin/game.exe"))));
+        assert!(!pattern.matches("Game", Some(&session("C:\\Games\\Other.exe"))));
+        assert!(!pattern.matches("Game", None));
+    }
+
+    #[test]
+    fn application() {
+        let pattern = StreamPattern {
+            name: None,
+            executable: Some("reaper*".into()),
+        };
+        let reaper = Application {
+            executable: Some("/opt/REAPER/reaper".into()),
+            name: None,
+        };
+        assert!(pattern.matches_application(&reaper));
+        assert!(!pattern.matches_application(&Application::default()));
+
+        let pattern = StreamPattern {
+            name: Some("org.gnome.*".into()),
+            executable: None,
+        };
+        let nautilus = Application {
+            executable: None,
+            name: Some("org.gnome.Nautilus".into()),
+        };
+        assert!(pattern.matches_application(&nautilus));
+        assert!(!pattern.matches_application(&reaper));
+    }
+}
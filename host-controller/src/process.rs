@@ -0,0 +1,284 @@
+//! Finds the audio stream that belongs to a focused window, when the audio comes from another
+//! process than the window itself.
+//!
+//! Browsers, Electron apps and many games play audio from a child or helper process, so the
+//! window's process often has no stream of its own. A [`ProcessTree`] relates the two through
+//! their parents and executables.
+
+use std::{collections::HashMap, io};
+
+pub type ProcessId = u32;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub parent: Option<ProcessId>,
+    /// Path or file name of the executable, whichever the system reports.
+    pub executable: Option<String>,
+}
+
+/// A snapshot of the running processes.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessTree {
+    processes: HashMap<ProcessId, ProcessInfo>,
+}
+
+impl ProcessTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, process_id: ProcessId, info: ProcessInfo) {
+        self.processes.insert(process_id, info);
+    }
+
+    /// Reads the running processes from `/proc`.
+    #[cfg(target_os = "linux")]
+    pub fn snapshot() -> io::Result<Self> {
+        let mut tree = Self::new();
+        for entry in std::fs::read_dir("/proc")? {
+            let entry = entry?;
+            let process_id = match entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.parse().ok())
+            {
+                Some(x) => x,
+                None => continue,
+            };
+            // The process may have exited in the meantime.
+            let stat = match std::fs::read_to_string(entry.path().join("stat")) {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            tree.insert(
+                process_id,
+                ProcessInfo {
+                    parent: parse_stat_parent(&stat),
+                    executable: read_executable(process_id),
+                },
+            );
+        }
+        Ok(tree)
+    }
+
+    /// Reads the running processes with the Tool Help API.
+    #[cfg(windows)]
+    pub fn snapshot() -> io::Result<Self> {
+        use crate::bindings::Windows::Win32::{
+            Foundation::CloseHandle,
+            System::Diagnostics::ToolHelp::{
+                CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W,
+                TH32CS_SNAPPROCESS,
+            },
+        };
+
+        let mut tree = Self::new();
+        unsafe {
+            let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0);
+            if snapshot.is_invalid() {
+                return Err(io::Error::last_os_error());
+            }
+            let mut entry = PROCESSENTRY32W {
+                dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32,
+                ..Default::default()
+            };
+            let mut more = Process32FirstW(snapshot, &mut entry).as_bool();
+            while more {
+                let length = entry
+                    .szExeFile
+                    .iter()
+                    .position(|&c| c == 0)
+                    .unwrap_or(entry.szExeFile.len());
+                tree.insert(
+                    entry.th32ProcessID,
+                    ProcessInfo {
+                        parent: Some(entry.th32ParentProcessID).filter(|&parent| parent != 0),
+                        executable: Some(String::from_utf16_lossy(&entry.szExeFile[..length])),
+                    },
+                );
+                more = Process32NextW(snapshot, &mut entry).as_bool();
+            }
+            CloseHandle(snapshot);
+        }
+        Ok(tree)
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    pub fn snapshot() -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "process snapshots are not supported on this platform",
+        ))
+    }
+
+    /// The executable of a single process, without reading the others.
+    #[cfg(target_os = "linux")]
+    pub fn executable(process_id: ProcessId) -> Option<String> {
+        read_executable(process_id)
+    }
+
+    /// The executable of a single process.
+    #[cfg(not(target_os = "linux"))]
+    pub fn executable(process_id: ProcessId) -> Option<String> {
+        Self::snapshot().ok()?.get(process_id)?.executable.clone()
+    }
+
+    pub fn get(&self, process_id: ProcessId) -> Option<&ProcessInfo> {
+        self.processes.get(&process_id)
+    }
+
+    /// The parent of the process, its parent, and so on.
+    pub fn ancestors(&self, process_id: ProcessId) -> impl Iterator<Item = ProcessId> + '_ {
+        let mut current = process_id;
+        // Process IDs are reused, so a stale parent could lead around in a circle.
+        let mut remaining = self.processes.len();
+        std::iter::from_fn(move || {
+            let parent = self.get(current)?.parent?;
+            if remaining == 0 || parent == process_id {
+                return None;
+            }
+            remaining -= 1;
+            current = parent;
+            Some(parent)
+        })
+    }
+
+    /// Picks the process among `candidates` (the processes that have audio streams) that
+    /// belongs to the focused process. In order of preference, that is:
+    ///
+    /// 1. the focused process itself,
+    /// 2. its closest descendant, like the audio process of a browser,
+    /// 3. its closest ancestor with the same executable,
+    /// 4. any other process with the same executable, like a helper that was started by a
+    ///    launcher or service.
+    pub fn resolve(&self, focused: ProcessId, candidates: &[ProcessId]) -> Option<ProcessId> {
+        if candidates.contains(&focused) {
+            return Some(focused);
+        }
+
+        let descendant = candidates
+            .iter()
+            .filter_map(|&candidate| {
+                self.ancestors(candidate)
+                    .position(|ancestor| ancestor == focused)
+                    .map(|depth| (depth, candidate))
+            })
+            .min();
+        if let Some((_, candidate)) = descendant {
+            return Some(candidate);
+        }
+
+        let executable = self.get(focused)?.executable.as_ref()?;
+        let same_executable = |process_id: ProcessId| {
+            self.get(process_id)
+                .and_then(|info| info.executable.as_ref())
+                == Some(executable)
+        };
+        if let Some(ancestor) = self
+            .ancestors(focused)
+            .take_while(|&ancestor| same_executable(ancestor))
+            .find(|ancestor| candidates.contains(ancestor))
+        {
+            return Some(ancestor);
+        }
+        candidates
+            .iter()
+            .copied()
+            .filter(|&candidate| same_executable(candidate))
+            .min()
+    }
+}
+
+/// The executable from `/proc/<pid>/exe`. Those of other users' processes can't be read.
+#[cfg(target_os = "linux")]
+fn read_executable(process_id: ProcessId) -> Option<String> {
+    let path = std::fs::read_link(format!("/proc/{}/exe", process_id)).ok()?;
+    let path = path.to_string_lossy();
+    // Executables that were replaced by an update are still the same program.
+    Some(path.strip_suffix(" (deleted)").unwrap_or(&path).to_string())
+}
+
+/// The parent process ID from the contents of `/proc/<pid>/stat`.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_stat_parent(stat: &str) -> Option<ProcessId> {
+    // The command name comes first, in parentheses. It may contain spaces and parentheses itself,
+    // so the other fields start after the last one.
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+    let _state = fields.next()?;
+    match fields.next()?.parse().ok()? {
+        0 => None,
+        parent => Some(parent),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(parent: Option<ProcessId>, executable: &str) -> ProcessInfo {
+        ProcessInfo {
+            parent,
+            executable: Some(executable.into()),
+        }
+    }
+
+    fn tree() -> ProcessTree {
+        let mut tree = ProcessTree::new();
+        tree.insert(1, process(None, "init"));
+        tree.insert(10, process(Some(1), "shell"));
+        tree.insert(20, process(Some(10), "browser"));
+        tree.insert(21, process(Some(20), "browser"));
+        tree.insert(22, process(Some(21), "browser"));
+        tree.insert(30, process(Some(10), "launcher"));
+        tree.insert(31, process(Some(30), "game"));
+        tree.insert(40, process(Some(1), "game"));
+        tree
+    }
+
+    #[test]
+    fn resolve() {
+        let tree = tree();
+        assert_eq!(tree.resolve(20, &[20, 22]), Some(20));
+        // The closest descendant wins.
+        assert_eq!(tree.resolve(20, &[22, 21]), Some(21));
+        assert_eq!(tree.resolve(21, &[20, 31]), Some(20));
+        // Ancestors with other executables don't count, the shell is not part of the browser.
+        assert_eq!(tree.resolve(20, &[10]), None);
+        assert_eq!(tree.resolve(31, &[40]), Some(40));
+        assert_eq!(tree.resolve(31, &[30]), None);
+        assert_eq!(tree.resolve(99, &[20]), None);
+    }
+
+    #[test]
+    fn ancestors() {
+        let mut tree = tree();
+        assert_eq!(tree.ancestors(22).collect::<Vec<_>>(), vec![21, 20, 10, 1]);
+        // A reused process ID that points back down doesn't loop forever.
+        tree.insert(1, process(Some(22), "init"));
+        assert_eq!(tree.ancestors(22).collect::<Vec<_>>(), vec![21, 20, 10, 1]);
+    }
+
+    #[test]
+    fn stat_parent() {
+        assert_eq!(
+            parse_stat_parent("1234 (Web Content) S 1200 1234 1234 0 -1"),
+            Some(1200)
+        );
+        assert_eq!(parse_stat_parent("5 (a) b) (c) R 4 5 5"), Some(4));
+        assert_eq!(parse_stat_parent("1 (init) S 0 1 1"), None);
+        assert_eq!(parse_stat_parent("garbage"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn snapshot_finds_this_process() {
+        let tree = ProcessTree::snapshot().unwrap();
+        let info = tree.get(std::process::id()).unwrap();
+        assert!(info.parent.is_some());
+        let executable = std::env::current_exe().unwrap();
+        assert_eq!(
+            info.executable.as_deref(),
+            Some(executable.to_string_lossy().as_ref())
+        );
+    }
+}
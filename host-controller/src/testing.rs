@@ -0,0 +1,266 @@
+//! Scripted mock backends, for testing the core without any audio or control hardware.
+//!
+//! [`Harness`] runs a [`Core`] with a [`MockAudioBackend`] and a [`MockControlBackend`], on the
+//! calling thread. Every event or input that is sent is processed completely before the call
+//! returns, so the recorded outputs are deterministic.
+//!
+//! Time stands still in the harness, unless it is moved forward with [`Harness::advance`].
+
+use crate::{
+    audio::{AudioBackend, AudioControl, AudioEvent, AudioHandle},
+    clock::Clock,
+    config::Config,
+    control::{ControlBackend, ControlHandle, ControlInput, ControlOutput},
+    core::{Command, Core},
+};
+use smol::channel::Sender;
+use std::{
+    cell::{Cell, RefCell},
+    convert::Infallible,
+    error::Error,
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    rc::Rc,
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+/// An audio backend that hands its handle over to the [`Harness`], and otherwise does nothing.
+pub struct MockAudioBackend {
+    handle: Rc<RefCell<Option<AudioHandle>>>,
+}
+
+impl AudioBackend for MockAudioBackend {
+    type Error = Infallible;
+
+    fn start(self, handle: AudioHandle) -> Pin<Box<dyn Future<Output = Result<(), Self::Error>>>> {
+        *self.handle.borrow_mut() = Some(handle);
+        Box::pin(smol::future::pending())
+    }
+}
+
+/// A control backend that hands its handle over to the [`Harness`], and otherwise does nothing.
+pub struct MockControlBackend {
+    handle: Rc<RefCell<Option<ControlHandle>>>,
+}
+
+impl ControlBackend for MockControlBackend {
+    type Error = Infallible;
+
+    fn start(
+        self,
+        handle: ControlHandle,
+    ) -> Pin<Box<dyn Future<Output = Result<(), Self::Error>>>> {
+        *self.handle.borrow_mut() = Some(handle);
+        Box::pin(smol::future::pending())
+    }
+}
+
+/// A clock that only moves when it is told to.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Rc<Cell<Instant>>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            now: Rc::new(Cell::new(Instant::now())),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.set(self.now.get() + duration);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.now.get()
+    }
+
+    /// The returned future doesn't wake its task, it has to be polled again after advancing
+    /// the clock, like [`Harness::settle`] does.
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Future<Output = ()>>> {
+        let now = self.now.clone();
+        Box::pin(smol::future::poll_fn(move |_| {
+            if now.get() >= deadline {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }))
+    }
+}
+
+/// A single step of a script.
+#[derive(Debug)]
+pub enum Step {
+    Audio(AudioEvent),
+    Control(ControlInput),
+    Command(Command),
+}
+
+impl From<AudioEvent> for Step {
+    fn from(event: AudioEvent) -> Self {
+        Self::Audio(event)
+    }
+}
+
+impl From<ControlInput> for Step {
+    fn from(input: ControlInput) -> Self {
+        Self::Control(input)
+    }
+}
+
+impl From<Command> for Step {
+    fn from(command: Command) -> Self {
+        Self::Command(command)
+    }
+}
+
+type MockCore = Core<MockAudioBackend, MockControlBackend>;
+
+type CoreFuture = Pin<Box<dyn Future<Output = Result<(), Box<dyn Error>>>>>;
+
+/// Drives a [`Core`] step by step, and records everything it sends to the backends.
+pub struct Harness {
+    core: CoreFuture,
+    audio_handle: Rc<RefCell<Option<AudioHandle>>>,
+    control_handle: Rc<RefCell<Option<ControlHandle>>>,
+    command_tx: Sender<Command>,
+    clock: ManualClock,
+    audio_controls: Vec<AudioControl>,
+    control_outputs: Vec<ControlOutput>,
+}
+
+impl Harness {
+    /// Starts a core with the given configuration. The configuration is never saved to disk.
+    pub fn new(config: Config) -> Self {
+        Self::start(|core| core.with_config(config))
+    }
+
+    /// Starts a core that loads its configuration from the file, and saves it there.
+    pub fn with_config_path(path: PathBuf) -> Self {
+        Self::start(|core| core.with_config_path(path))
+    }
+
+    fn start(configure: impl FnOnce(MockCore) -> MockCore) -> Self {
+        let audio_handle = Rc::new(RefCell::new(None));
+        let control_handle = Rc::new(RefCell::new(None));
+        let core = Core::new(
+            MockAudioBackend {
+                handle: audio_handle.clone(),
+            },
+            MockControlBackend {
+                handle: control_handle.clone(),
+            },
+        );
+        let clock = ManualClock::new();
+        let core = configure(core).with_clock(clock.clone());
+        let command_tx = core.command_sender();
+
+        let mut harness = Self {
+            core: Box::pin(core.run_async()),
+            audio_handle,
+            control_handle,
+            command_tx,
+            clock,
+            audio_controls: Vec::new(),
+            control_outputs: Vec::new(),
+        };
+        // Let the backends receive their handles.
+        harness.settle();
+        harness
+    }
+
+    /// Sends an event from the audio backend, and waits until it has been processed.
+    pub fn audio(&mut self, event: AudioEvent) {
+        {
+            let handle = self.audio_handle.borrow();
+            let handle = handle.as_ref().expect("audio backend not started");
+            smol::block_on(handle.send(event));
+        }
+        self.settle();
+    }
+
+    /// Sends an input from the control backend, and waits until it has been processed.
+    pub fn control(&mut self, input: ControlInput) {
+        {
+            let handle = self.control_handle.borrow();
+            let handle = handle.as_ref().expect("control backend not started");
+            smol::block_on(handle.send(input));
+        }
+        self.settle();
+    }
+
+    /// Sends an external command, and waits until it has been processed.
+    pub fn command(&mut self, command: Command) {
+        smol::block_on(self.command_tx.send(command)).expect("core stopped receiving commands");
+        self.settle();
+    }
+
+    /// Moves the clock forward, and runs everything that was waiting for that time.
+    pub fn advance(&mut self, duration: Duration) {
+        self.clock.advance(duration);
+        self.settle();
+    }
+
+    /// Runs each step of the script in order.
+    pub fn play<I>(&mut self, script: I)
+    where
+        I: IntoIterator<Item = Step>,
+    {
+        for step in script {
+            match step {
+                Step::Audio(event) => self.audio(event),
+                Step::Control(input) => self.control(input),
+                Step::Command(command) => self.command(command),
+            }
+        }
+    }
+
+    /// The controls that were sent to the audio backend since the last call.
+    pub fn take_audio_controls(&mut self) -> Vec<AudioControl> {
+        std::mem::take(&mut self.audio_controls)
+    }
+
+    /// The outputs that were sent to the control backend since the last call.
+    pub fn take_control_outputs(&mut self) -> Vec<ControlOutput> {
+        std::mem::take(&mut self.control_outputs)
+    }
+
+    /// Discards everything that has been recorded so far.
+    pub fn clear(&mut self) {
+        self.audio_controls.clear();
+        self.control_outputs.clear();
+    }
+
+    /// Runs the core until it is waiting for more input, and records its outputs.
+    ///
+    /// The core handles everything that is ready in a single poll, since sending to the backends
+    /// never blocks.
+    pub fn settle(&mut self) {
+        if let Some(result) = smol::block_on(smol::future::poll_once(self.core.as_mut())) {
+            panic!("core exited: {:?}", result);
+        }
+
+        if let Some(handle) = &*self.audio_handle.borrow() {
+            while let Some(Some(control)) = smol::block_on(smol::future::poll_once(handle.recv())) {
+                self.audio_controls.push(control);
+            }
+        }
+        if let Some(handle) = &*self.control_handle.borrow() {
+            while let Ok(output) = handle.try_recv() {
+                self.control_outputs.push(output);
+            }
+        }
+    }
+}
@@ -0,0 +1,251 @@
+use crate::audio::VolumeLevel;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The amount that a single encoder step moves a channel, if it isn't configured.
+pub const DEFAULT_VOLUME_STEP: f64 = 0.02;
+
+/// Maps the position of a channel's control (from 0 to 1) to the volume of its streams, and
+/// back.
+///
+/// The core applies the curve to every `SetVolume` and `StepVolume` input, so a channel behaves
+/// the same way regardless of the audio backend.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum VolumeCurve {
+    /// The position is used as the volume directly.
+    #[default]
+    Linear,
+    /// Each step changes the volume by the same number of decibels, which gives finer control
+    /// at low volumes. The bottom of the range is silent.
+    Decibel {
+        /// The range of the control in decibels, below full volume.
+        range_db: f64,
+    },
+    /// Linear interpolation between `[position, volume]` points, which have to be sorted by
+    /// position, with the volume increasing too.
+    Table { points: Vec<[f64; 2]> },
+}
+
+impl VolumeCurve {
+    /// The volume for the given control position.
+    pub fn volume(&self, position: f32) -> VolumeLevel {
+        let position = f64::from(position).clamp(0.0, 1.0);
+        let volume = match self {
+            Self::Linear => position,
+            Self::Decibel { range_db } => {
+                if position <= 0.0 {
+                    0.0
+                } else {
+                    let db = (position - 1.0) * range_db;
+                    10f64.powf(db / 20.0)
+                }
+            }
+            Self::Table { points } => interpolate(points.iter().map(|&[x, y]| (x, y)), position),
+        };
+        volume.clamp(0.0, 1.0) as VolumeLevel
+    }
+
+    /// The control position that corresponds to the given volume. This is the inverse of
+    /// [`VolumeCurve::volume`].
+    pub fn position(&self, volume: VolumeLevel) -> f32 {
+        let volume = f64::from(volume).clamp(0.0, 1.0);
+        let position = match self {
+            Self::Linear => volume,
+            Self::Decibel { range_db } => {
+                if volume <= 0.0 || *range_db <= 0.0 {
+                    0.0
+                } else {
+                    1.0 + 20.0 * volume.log10() / range_db
+                }
+            }
+            Self::Table { points } => interpolate(points.iter().map(|&[x, y]| (y, x)), volume),
+        };
+        position.clamp(0.0, 1.0) as f32
+    }
+
+    /// The volume after moving the control by the given number of steps from where it is for
+    /// the current volume. The steps may be fractional, after acceleration.
+    pub fn step(&self, volume: VolumeLevel, steps: f64, step_size: f64) -> VolumeLevel {
+        let position = f64::from(self.position(volume)) + steps * step_size;
+        self.volume(position as f32)
+    }
+}
+
+/// The range that a channel keeps the volume of its streams in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VolumeLimits {
+    pub min: f64,
+    pub max: f64,
+    /// What happens when another application changes the volume to outside of the limits.
+    pub external: ExternalChange,
+}
+
+impl Default for VolumeLimits {
+    fn default() -> Self {
+        Self {
+            min: 0.0,
+            max: 1.0,
+            external: ExternalChange::default(),
+        }
+    }
+}
+
+impl VolumeLimits {
+    pub fn clamp(&self, volume: VolumeLevel) -> VolumeLevel {
+        // Not `f32::clamp`, which panics if the limits are the wrong way around.
+        volume
+            .max(self.min as VolumeLevel)
+            .min(self.max as VolumeLevel)
+    }
+}
+
+/// How a channel reacts when the volume of one of its streams is changed from outside to a value
+/// that is not within its limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExternalChange {
+    /// The volume is set back to the nearest limit.
+    #[default]
+    Clamp,
+    /// The volume is left alone, and the channel shows it as it is.
+    Display,
+}
+
+/// Makes fast knob turns move further than the same number of slow steps.
+///
+/// The speed is measured from the time between consecutive step inputs of a channel. Below
+/// `threshold`, every step moves the normal step size; above it, steps are multiplied by a factor
+/// that grows with the speed, up to `max_factor`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Acceleration {
+    /// The speed where acceleration begins, in steps per second.
+    pub threshold: f64,
+    /// How much the factor grows for each step per second above the threshold.
+    pub gain: f64,
+    /// The largest factor that a step can be multiplied by. The default of 1 disables
+    /// acceleration.
+    pub max_factor: f64,
+}
+
+impl Default for Acceleration {
+    fn default() -> Self {
+        Self {
+            threshold: 8.0,
+            gain: 0.1,
+            max_factor: 1.0,
+        }
+    }
+}
+
+impl Acceleration {
+    /// Inputs that are further apart than this are always treated as slow.
+    const TIMEOUT: Duration = Duration::from_millis(250);
+
+    /// The factor for a step input, given the time since the previous one on the same channel.
+    pub fn factor(&self, steps: i32, elapsed: Option<Duration>) -> f64 {
+        let elapsed = match elapsed {
+            Some(x) if x < Self::TIMEOUT => x,
+            _ => return 1.0,
+        };
+        // Reports can arrive back-to-back, so don't let the speed go to infinity.
+        let seconds = elapsed.as_secs_f64().max(0.001);
+        let speed = f64::from(steps.abs()) / seconds;
+        let factor = 1.0 + (speed - self.threshold).max(0.0) * self.gain;
+        factor.min(self.max_factor).max(1.0)
+    }
+}
+
+/// Piecewise-linear interpolation between `(x, y)` points sorted by `x`. Values outside of the
+/// points are clamped to the first or last point.
+fn interpolate<I>(points: I, x: f64) -> f64
+where
+    I: IntoIterator<Item = (f64, f64)>,
+{
+    let mut previous: Option<(f64, f64)> = None;
+    for (x1, y1) in points {
+        if x <= x1 {
+            return match previous {
+                Some((x0, y0)) if x1 > x0 => y0 + (x - x0) * (y1 - y0) / (x1 - x0),
+                _ => y1,
+            };
+        }
+        previous = Some((x1, y1));
+    }
+    // Past the last point, or no points at all.
+    previous.map(|(_, y)| y).unwrap_or(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn decibel() {
+        let curve = VolumeCurve::Decibel { range_db: 60.0 };
+        assert_close(curve.volume(1.0), 1.0);
+        assert_close(curve.volume(0.5), 10f32.powf(-1.5));
+        assert_close(curve.volume(0.0), 0.0);
+        assert_close(curve.position(curve.volume(0.25)), 0.25);
+        assert_close(curve.position(0.0), 0.0);
+        // Steps are finer at low volumes than with the linear curve.
+        assert!(curve.step(0.01, 1.0, 0.02) - 0.01 < 0.02);
+    }
+
+    #[test]
+    fn table() {
+        let curve = VolumeCurve::Table {
+            points: vec![[0.0, 0.0], [0.5, 0.1], [1.0, 1.0]],
+        };
+        assert_close(curve.volume(0.25), 0.05);
+        assert_close(curve.volume(0.75), 0.55);
+        assert_close(curve.volume(2.0), 1.0);
+        assert_close(curve.position(0.1), 0.5);
+        assert_close(curve.position(0.55), 0.75);
+        assert_close(curve.step(0.1, -5.0, 0.05), 0.05);
+    }
+
+    #[test]
+    fn acceleration() {
+        let acceleration = Acceleration {
+            max_factor: 8.0,
+            ..Acceleration::default()
+        };
+        // The first step, and slow steps, are never accelerated.
+        assert_eq!(acceleration.factor(1, None), 1.0);
+        assert_eq!(
+            acceleration.factor(1, Some(Duration::from_millis(200))),
+            1.0
+        );
+        assert_eq!(acceleration.factor(3, Some(Duration::from_secs(1))), 1.0);
+        // 20 steps per second is 12 above the threshold.
+        let factor = acceleration.factor(1, Some(Duration::from_millis(50)));
+        assert!((factor - 2.2).abs() < 1e-9);
+        // Bigger reports at the same interval are faster.
+        assert!(acceleration.factor(2, Some(Duration::from_millis(50))) > factor);
+        assert_eq!(acceleration.factor(10, Some(Duration::from_millis(1))), 8.0);
+        // Acceleration is opt-in.
+        assert_eq!(
+            Acceleration::default().factor(10, Some(Duration::from_millis(1))),
+            1.0
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let limits = VolumeLimits {
+            min: 0.05,
+            max: 0.7,
+            ..VolumeLimits::default()
+        };
+        assert_eq!(limits.clamp(0.5), 0.5);
+        assert_eq!(limits.clamp(0.9), 0.7);
+        assert_eq!(limits.clamp(0.0), 0.05);
+    }
+}
@@ -0,0 +1,1432 @@
+//! End-to-end tests of the core, driven through the mock backends.
+
+use std::time::Duration;
+use windowmaster::{
+    action::{Action, MediaKey},
+    audio::{
+        Application, AudioControl, AudioEvent, Direction, MediaControl, StreamControl, StreamEvent,
+        StreamId, StreamIdentity, StreamInfoBuilder, StreamState,
+    },
+    config::{
+        AutoProfile, ChannelBindings, ChannelKey, ChannelMode, Config, DeviceSettings, Ducking,
+        FocusRule, Profile, Target, TargetKind, VolumeMode,
+    },
+    control::{
+        ChannelInput, ChannelOutput, ControlInput, ControlOutput, DeviceId, DeviceInfoBuilder,
+        MenuItem,
+    },
+    core::Command,
+    gesture::Gestures,
+    pattern::StreamPattern,
+    testing::{Harness, Step},
+    volume::{Acceleration, ExternalChange, VolumeCurve, VolumeLimits},
+};
+
+const SERIAL: &str = "TEST0001";
+
+fn state(volume: f32, muted: bool) -> StreamState {
+    StreamState { volume, muted }
+}
+
+fn open_stream(
+    harness: &mut Harness,
+    name: &str,
+    initial_state: StreamState,
+    parent: Option<StreamId>,
+) -> StreamId {
+    let stream_id = StreamId::new();
+    let mut builder = StreamInfoBuilder::new(name.into()).with_initial_state(initial_state);
+    if let Some(parent) = parent {
+        builder = builder.with_parent(parent);
+    }
+    harness.audio(AudioEvent::StreamOpened {
+        stream_id,
+        stream_info: builder.build(),
+    });
+    stream_id
+}
+
+fn add_device(harness: &mut Harness, num_channels: usize) -> DeviceId {
+    let device_id = DeviceId::new();
+    harness.control(ControlInput::DeviceAdded(
+        device_id,
+        DeviceInfoBuilder::new("WindowMaster".into(), num_channels)
+            .with_serial_number(SERIAL.into())
+            .build(),
+    ));
+    device_id
+}
+
+fn input(harness: &mut Harness, device_id: DeviceId, channel: usize, input: ChannelInput) {
+    harness.control(ControlInput::ChannelInput(device_id, channel, input));
+}
+
+fn output(device_id: DeviceId, channel: usize, output: ChannelOutput) -> ControlOutput {
+    ControlOutput::ChannelOutput(device_id, channel, output)
+}
+
+fn control(stream_id: StreamId, stream_control: StreamControl) -> AudioControl {
+    AudioControl::StreamControl {
+        stream_id,
+        stream_control,
+    }
+}
+
+fn highlighted(text: &str, index: usize, count: usize, depth: usize) -> ChannelOutput {
+    ChannelOutput::MenuHighlighted(MenuItem {
+        text: text.into(),
+        index,
+        count,
+        depth,
+    })
+}
+
+/// Bindings for channel 0 of the test device, with the default settings.
+fn channel_0(targets: Vec<Target>) -> ChannelBindings {
+    ChannelBindings {
+        targets,
+        ..ChannelBindings::new(ChannelKey {
+            device: SERIAL.into(),
+            channel: 0,
+        })
+    }
+}
+
+/// A config with a single profile, which contains the given bindings.
+fn bound_config(bindings: ChannelBindings) -> Config {
+    Config {
+        profiles: vec![Profile {
+            name: "Default".into(),
+            bindings: vec![bindings],
+        }],
+        ..Config::default()
+    }
+}
+
+fn stream_target(name: &str) -> Target {
+    Target::new(TargetKind::Stream {
+        name: name.into(),
+        identity: None,
+    })
+}
+
+#[test]
+fn menu_binds_stream_in_submenu() {
+    let mut harness = Harness::new(Config::default());
+    let speakers = open_stream(&mut harness, "Speakers", state(0.8, false), None);
+    let firefox = open_stream(&mut harness, "Firefox", state(0.5, false), Some(speakers));
+    open_stream(&mut harness, "Headphones", state(0.3, false), None);
+    let device_id = add_device(&mut harness, 2);
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::OpenMenu);
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![
+            output(device_id, 0, ChannelOutput::MenuOpened),
+            output(device_id, 0, highlighted("None", 0, 5, 0)),
+        ]
+    );
+
+    for _ in 0..4 {
+        input(&mut harness, device_id, 0, ChannelInput::MenuNext);
+    }
+    assert_eq!(
+        harness.take_control_outputs().pop(),
+        Some(output(device_id, 0, highlighted("Speakers >", 4, 5, 0)))
+    );
+
+    input(&mut harness, device_id, 0, ChannelInput::MenuSelect);
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(device_id, 0, highlighted("Back", 0, 3, 1))]
+    );
+
+    input(&mut harness, device_id, 0, ChannelInput::MenuNext);
+    input(&mut harness, device_id, 0, ChannelInput::MenuNext);
+    harness.clear();
+    input(&mut harness, device_id, 0, ChannelInput::MenuSelect);
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![
+            output(device_id, 0, ChannelOutput::StateChanged(state(0.5, false))),
+            output(device_id, 0, ChannelOutput::MenuClosed),
+        ]
+    );
+
+    input(&mut harness, device_id, 0, ChannelInput::StepVolume(1));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.5 + 0.02))]
+    );
+    input(&mut harness, device_id, 1, ChannelInput::StepVolume(1));
+    assert_eq!(harness.take_audio_controls(), vec![]);
+}
+
+#[test]
+fn menu_back_returns_to_parent() {
+    let mut harness = Harness::new(Config::default());
+    let speakers = open_stream(&mut harness, "Speakers", state(0.8, false), None);
+    open_stream(&mut harness, "Firefox", state(0.5, false), Some(speakers));
+    let device_id = add_device(&mut harness, 1);
+
+    harness.play(
+        vec![
+            ChannelInput::OpenMenu,
+            ChannelInput::MenuNext,
+            ChannelInput::MenuNext,
+            ChannelInput::MenuNext,
+            ChannelInput::MenuSelect,
+        ]
+        .into_iter()
+        .map(|input| Step::from(ControlInput::ChannelInput(device_id, 0, input))),
+    );
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::MenuSelect);
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(device_id, 0, highlighted("Speakers >", 3, 4, 0))]
+    );
+
+    input(&mut harness, device_id, 0, ChannelInput::CloseMenu);
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(device_id, 0, ChannelOutput::MenuClosed)]
+    );
+    input(&mut harness, device_id, 0, ChannelInput::MenuSelect);
+    assert_eq!(harness.take_control_outputs(), vec![]);
+}
+
+#[test]
+fn inputs_have_their_own_menu() {
+    let mut harness = Harness::new(Config::default());
+    open_stream(&mut harness, "Speakers", state(0.8, false), None);
+    let microphone = StreamId::new();
+    harness.audio(AudioEvent::StreamOpened {
+        stream_id: microphone,
+        stream_info: StreamInfoBuilder::new("Microphone".into())
+            .with_initial_state(state(0.6, false))
+            .with_direction(Direction::Input)
+            .build(),
+    });
+    let device_id = add_device(&mut harness, 1);
+
+    input(&mut harness, device_id, 0, ChannelInput::OpenMenu);
+    for _ in 0..4 {
+        input(&mut harness, device_id, 0, ChannelInput::MenuNext);
+    }
+    assert_eq!(
+        harness.take_control_outputs().pop(),
+        Some(output(device_id, 0, highlighted("Inputs >", 4, 5, 0)))
+    );
+    input(&mut harness, device_id, 0, ChannelInput::MenuSelect);
+    input(&mut harness, device_id, 0, ChannelInput::MenuNext);
+    assert_eq!(
+        harness.take_control_outputs().pop(),
+        Some(output(
+            device_id,
+            0,
+            highlighted("Default Input Device", 1, 3, 1)
+        ))
+    );
+    input(&mut harness, device_id, 0, ChannelInput::MenuSelect);
+    harness.clear();
+
+    harness.audio(AudioEvent::DefaultInputDeviceChanged {
+        stream_id: Some(microphone),
+    });
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(0.6, false))
+        )]
+    );
+    input(&mut harness, device_id, 0, ChannelInput::ToggleMuted);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(microphone, StreamControl::ToggleMuted)]
+    );
+}
+
+#[test]
+fn saved_binding_is_restored_and_follows_state() {
+    let mut harness = Harness::new(bound_config(channel_0(vec![stream_target("Firefox")])));
+    let device_id = add_device(&mut harness, 2);
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![
+            output(device_id, 0, ChannelOutput::StateChanged(state(0.0, false))),
+            output(device_id, 1, ChannelOutput::StateChanged(state(0.0, false))),
+        ]
+    );
+
+    let firefox = open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    assert_eq!(
+        harness.take_control_outputs().pop(),
+        Some(output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(0.5, false))
+        ))
+    );
+
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: firefox,
+        stream_event: StreamEvent::StateChanged(state(0.7, true)),
+    });
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(0.7, true))
+        )]
+    );
+
+    input(&mut harness, device_id, 0, ChannelInput::ToggleMuted);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::ToggleMuted)]
+    );
+
+    harness.audio(AudioEvent::StreamClosed { stream_id: firefox });
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(0.0, false))
+        )]
+    );
+}
+
+#[test]
+fn saved_device_without_role_is_restored() {
+    let identity = |role: Option<&str>| StreamIdentity::Device {
+        endpoint_id: "{0.0.0.00000000}.{speakers}".into(),
+        role: role.map(String::from),
+    };
+    let mut harness = Harness::new(bound_config(channel_0(vec![Target::new(
+        TargetKind::Stream {
+            name: "Speakers".into(),
+            identity: Some(identity(None)),
+        },
+    )])));
+    let device_id = add_device(&mut harness, 1);
+    harness.clear();
+
+    // Identities that were saved before the backend reported roles still match.
+    let speakers = StreamId::new();
+    harness.audio(AudioEvent::StreamOpened {
+        stream_id: speakers,
+        stream_info: StreamInfoBuilder::new("Realtek Speakers".into())
+            .with_initial_state(state(0.5, false))
+            .with_identity(identity(Some("speaker")))
+            .build(),
+    });
+    assert_eq!(
+        harness.take_control_outputs().pop(),
+        Some(output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(0.5, false))
+        ))
+    );
+}
+
+#[test]
+fn active_window_follows_focus() {
+    let mut harness = Harness::new(bound_config(channel_0(vec![Target::new(
+        TargetKind::ActiveWindow,
+    )])));
+    let firefox = open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    let game = open_stream(&mut harness, "Game", state(0.9, false), None);
+    let device_id = add_device(&mut harness, 1);
+    harness.clear();
+
+    harness.audio(AudioEvent::WindowFocusChanged {
+        stream_id: Some(game),
+        application: None,
+    });
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(0.9, false))
+        )]
+    );
+    input(&mut harness, device_id, 0, ChannelInput::SetMuted(true));
+
+    harness.audio(AudioEvent::WindowFocusChanged {
+        stream_id: Some(firefox),
+        application: None,
+    });
+    input(&mut harness, device_id, 0, ChannelInput::SetVolume(0.25));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![
+            control(game, StreamControl::SetMuted(true)),
+            control(firefox, StreamControl::SetVolume(0.25)),
+        ]
+    );
+}
+
+#[test]
+fn closed_focused_stream_is_unbound() {
+    let mut config = bound_config(channel_0(vec![Target::new(TargetKind::ActiveWindow)]));
+    config.gestures = Gestures {
+        click: Action::Player(MediaKey::PlayPause),
+        ..Gestures::default()
+    };
+    let mut harness = Harness::new(config);
+    let game = open_stream(&mut harness, "Game", state(0.9, false), None);
+    let device_id = add_device(&mut harness, 1);
+    harness.audio(AudioEvent::WindowFocusChanged {
+        stream_id: Some(game),
+        application: None,
+    });
+    harness.clear();
+
+    harness.audio(AudioEvent::StreamClosed { stream_id: game });
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(StreamState::default())
+        )]
+    );
+    input(&mut harness, device_id, 0, ChannelInput::Button(true));
+    input(&mut harness, device_id, 0, ChannelInput::Button(false));
+    input(&mut harness, device_id, 0, ChannelInput::SetVolume(0.25));
+    assert_eq!(harness.take_audio_controls(), vec![]);
+}
+
+#[test]
+fn pattern_binding_controls_all_matching_streams() {
+    let pattern = StreamPattern {
+        name: Some("firefox*".into()),
+        executable: None,
+    };
+    let mut harness = Harness::new(bound_config(channel_0(vec![Target::new(
+        TargetKind::Pattern(pattern),
+    )])));
+    let device_id = add_device(&mut harness, 1);
+    let firefox = open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    open_stream(&mut harness, "Chrome", state(0.6, false), None);
+    let nightly = open_stream(&mut harness, "Firefox Nightly", state(0.2, true), None);
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::StepVolume(-2));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![
+            control(firefox, StreamControl::SetVolume(0.5 - 2.0 * 0.02)),
+            control(nightly, StreamControl::SetVolume(0.2 - 2.0 * 0.02)),
+        ]
+    );
+    harness.clear();
+
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: firefox,
+        stream_event: StreamEvent::StateChanged(state(0.1, true)),
+    });
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(0.2 - 2.0 * 0.02, true))
+        )]
+    );
+}
+
+#[test]
+fn group_volume_keeps_ratios() {
+    let pattern = StreamPattern {
+        name: Some("firefox*".into()),
+        executable: None,
+    };
+    let mut harness = Harness::new(bound_config(ChannelBindings {
+        volume_mode: VolumeMode::Group,
+        ..channel_0(vec![Target::new(TargetKind::Pattern(pattern))])
+    }));
+    let device_id = add_device(&mut harness, 1);
+    let firefox = open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    let nightly = open_stream(&mut harness, "Firefox Nightly", state(0.25, false), None);
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::SetVolume(1.0));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![
+            control(firefox, StreamControl::SetVolume(1.0)),
+            control(nightly, StreamControl::SetVolume(0.5)),
+        ]
+    );
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(1.0, false))
+        )]
+    );
+
+    // The ratios survive turning the whole group down to zero.
+    input(&mut harness, device_id, 0, ChannelInput::SetVolume(0.0));
+    input(&mut harness, device_id, 0, ChannelInput::SetVolume(0.5));
+    assert_eq!(
+        harness.take_audio_controls()[2..],
+        [
+            control(firefox, StreamControl::SetVolume(0.5)),
+            control(nightly, StreamControl::SetVolume(0.25)),
+        ]
+    );
+}
+
+#[test]
+fn volume_curve_is_applied_in_core() {
+    let curve = VolumeCurve::Table {
+        points: vec![[0.0, 0.0], [0.5, 0.1], [1.0, 1.0]],
+    };
+    let mut harness = Harness::new(bound_config(channel_0(vec![Target {
+        volume_curve: curve.clone(),
+        volume_step: 0.25,
+        ..stream_target("Firefox")
+    }])));
+    let firefox = open_stream(&mut harness, "Firefox", state(0.1, false), None);
+    let device_id = add_device(&mut harness, 1);
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(curve.position(0.1), false))
+        )]
+    );
+
+    input(&mut harness, device_id, 0, ChannelInput::StepVolume(1));
+    input(&mut harness, device_id, 0, ChannelInput::StepVolume(-3));
+    input(&mut harness, device_id, 0, ChannelInput::SetVolume(0.25));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![
+            control(firefox, StreamControl::SetVolume(curve.volume(0.75))),
+            control(firefox, StreamControl::SetVolume(0.0)),
+            control(firefox, StreamControl::SetVolume(curve.volume(0.25))),
+        ]
+    );
+}
+
+#[test]
+fn bindings_have_their_own_volume_curves() {
+    let path = std::env::temp_dir().join(format!(
+        "windowmaster-binding-curves-{}.toml",
+        std::process::id()
+    ));
+    let curve = VolumeCurve::Decibel { range_db: 40.0 };
+    let config = bound_config(channel_0(vec![
+        Target {
+            volume_curve: curve.clone(),
+            volume_step: 0.1,
+            ..stream_target("Firefox")
+        },
+        stream_target("Music"),
+    ]));
+    config.save(&path).unwrap();
+    assert_eq!(
+        Config::load(&path).unwrap().profiles[0].bindings[0].targets,
+        config.profiles[0].bindings[0].targets
+    );
+
+    let mut harness = Harness::with_config_path(path.clone());
+    let firefox = open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    let music = open_stream(&mut harness, "Music", state(0.5, false), None);
+    let device_id = add_device(&mut harness, 1);
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::SetVolume(0.5));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![
+            control(firefox, StreamControl::SetVolume(curve.volume(0.5))),
+            control(music, StreamControl::SetVolume(0.5)),
+        ]
+    );
+    input(&mut harness, device_id, 0, ChannelInput::StepVolume(1));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![
+            control(firefox, StreamControl::SetVolume(curve.volume(0.6))),
+            control(music, StreamControl::SetVolume(0.52)),
+        ]
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// The volumes that the stream is set to by two steps, the given time apart.
+fn two_steps(mut config: Config, devices: Vec<DeviceSettings>, interval: Duration) -> (f32, f32) {
+    config.devices = devices;
+    let mut harness = Harness::new(config);
+    let firefox = open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    let device_id = add_device(&mut harness, 1);
+    input(&mut harness, device_id, 0, ChannelInput::StepVolume(1));
+    harness.advance(interval);
+    input(&mut harness, device_id, 0, ChannelInput::StepVolume(1));
+    match harness.take_audio_controls()[..] {
+        [AudioControl::StreamControl {
+            stream_id: first_id,
+            stream_control: StreamControl::SetVolume(first),
+        }, AudioControl::StreamControl {
+            stream_id: second_id,
+            stream_control: StreamControl::SetVolume(second),
+        }] if first_id == firefox && second_id == firefox => (first, second),
+        ref controls => panic!("unexpected controls {:?}", controls),
+    }
+}
+
+#[test]
+fn fast_steps_are_accelerated() {
+    let config = || bound_config(channel_0(vec![stream_target("Firefox")]));
+    let fast = Duration::from_millis(50);
+    let assert_step = |(first, second): (f32, f32), step: f32| {
+        assert!(
+            (second - first - step).abs() < 1e-6,
+            "{} -> {}",
+            first,
+            second
+        );
+    };
+
+    // Acceleration is off unless it is configured.
+    let (first, second) = two_steps(config(), vec![], fast);
+    assert_eq!(first, 0.5 + 0.02);
+    assert_step((first, second), 0.02);
+
+    let enabled = Acceleration {
+        max_factor: 8.0,
+        ..Acceleration::default()
+    };
+    let device = |acceleration: &Acceleration| DeviceSettings {
+        device: SERIAL.into(),
+        acceleration: acceleration.clone(),
+    };
+    // 20 steps per second is 12 above the threshold, so the step is 2.2 times as large.
+    let (first, second) = two_steps(config(), vec![device(&enabled)], fast);
+    assert_eq!(first, 0.5 + 0.02);
+    assert_step((first, second), 0.02 * 2.2);
+    let slow = Duration::from_millis(300);
+    assert_step(two_steps(config(), vec![device(&enabled)], slow), 0.02);
+
+    // Channels can override the device's acceleration.
+    let steps = two_steps(
+        bound_config(ChannelBindings {
+            acceleration: Some(Acceleration::default()),
+            ..channel_0(vec![stream_target("Firefox")])
+        }),
+        vec![device(&enabled)],
+        fast,
+    );
+    assert_step(steps, 0.02);
+}
+
+#[test]
+fn volume_limits_are_enforced() {
+    let limits = |external| VolumeLimits {
+        min: 0.25,
+        max: 0.75,
+        external,
+    };
+    let mut harness = Harness::new(bound_config(channel_0(vec![Target {
+        limits: Some(limits(ExternalChange::Clamp)),
+        ..stream_target("Firefox")
+    }])));
+    let device_id = add_device(&mut harness, 1);
+    let firefox = open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    assert_eq!(harness.take_audio_controls(), vec![]);
+
+    input(&mut harness, device_id, 0, ChannelInput::SetVolume(1.0));
+    input(&mut harness, device_id, 0, ChannelInput::SetVolume(0.0));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![
+            control(firefox, StreamControl::SetVolume(0.75)),
+            control(firefox, StreamControl::SetVolume(0.25)),
+        ]
+    );
+
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: firefox,
+        stream_event: StreamEvent::StateChanged(state(1.0, false)),
+    });
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.75))]
+    );
+
+    // Streams that are opened outside of the limits are clamped too.
+    harness.audio(AudioEvent::StreamClosed { stream_id: firefox });
+    let firefox = open_stream(&mut harness, "Firefox", state(0.1, false), None);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.25))]
+    );
+
+    // Otherwise, the channel only shows the external volume.
+    let mut harness = Harness::new(bound_config(channel_0(vec![Target {
+        limits: Some(limits(ExternalChange::Display)),
+        ..stream_target("Firefox")
+    }])));
+    let device_id = add_device(&mut harness, 1);
+    let firefox = open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    harness.clear();
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: firefox,
+        stream_event: StreamEvent::StateChanged(state(1.0, false)),
+    });
+    assert_eq!(harness.take_audio_controls(), vec![]);
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(1.0, false))
+        )]
+    );
+    input(&mut harness, device_id, 0, ChannelInput::StepVolume(-1));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.75))]
+    );
+}
+
+#[test]
+fn bindings_have_their_own_volume_limits() {
+    let mut harness = Harness::new(bound_config(channel_0(vec![
+        Target {
+            limits: Some(VolumeLimits {
+                max: 0.7,
+                ..VolumeLimits::default()
+            }),
+            ..stream_target("Discord")
+        },
+        stream_target("Music"),
+    ])));
+    let discord = open_stream(&mut harness, "Discord", state(0.5, false), None);
+    let music = open_stream(&mut harness, "Music", state(0.5, false), None);
+    let device_id = add_device(&mut harness, 1);
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::SetVolume(1.0));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![
+            control(discord, StreamControl::SetVolume(0.7)),
+            control(music, StreamControl::SetVolume(1.0)),
+        ]
+    );
+
+    for &stream_id in &[discord, music] {
+        harness.audio(AudioEvent::StreamEvent {
+            stream_id,
+            stream_event: StreamEvent::StateChanged(state(0.9, false)),
+        });
+    }
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(discord, StreamControl::SetVolume(0.7))]
+    );
+}
+
+#[test]
+fn peak_meters_follow_bindings() {
+    let mut harness = Harness::new(Config {
+        meter_interval_ms: Some(50),
+        ..bound_config(channel_0(vec![Target::new(TargetKind::ActiveWindow)]))
+    });
+    let interval = Duration::from_millis(50);
+    let firefox = open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    let game = open_stream(&mut harness, "Game", state(0.9, false), None);
+    let device_id = add_device(&mut harness, 1);
+    assert_eq!(harness.take_audio_controls(), vec![]);
+
+    harness.audio(AudioEvent::WindowFocusChanged {
+        stream_id: Some(firefox),
+        application: None,
+    });
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![AudioControl::MeterSubscribe {
+            stream_id: firefox,
+            interval
+        }]
+    );
+    harness.clear();
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: firefox,
+        stream_event: StreamEvent::Peak(0.3),
+    });
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(device_id, 0, ChannelOutput::Peak(0.3))]
+    );
+
+    harness.audio(AudioEvent::WindowFocusChanged {
+        stream_id: Some(game),
+        application: None,
+    });
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![
+            AudioControl::MeterSubscribe {
+                stream_id: game,
+                interval
+            },
+            AudioControl::MeterUnsubscribe { stream_id: firefox },
+        ]
+    );
+
+    // Closed streams are not unsubscribed.
+    harness.audio(AudioEvent::StreamClosed { stream_id: game });
+    assert_eq!(harness.take_audio_controls(), vec![]);
+}
+
+#[test]
+fn ducking_lowers_bound_streams() {
+    let mut harness = Harness::new(Config {
+        ducking: Some(Ducking {
+            priority: vec![StreamPattern {
+                name: Some("Discord".into()),
+                executable: None,
+            }],
+            amount: 0.5,
+            threshold: 0.1,
+            attack_ms: 0,
+            hold_ms: 0,
+            release_ms: 0,
+        }),
+        ..bound_config(channel_0(vec![
+            stream_target("Firefox"),
+            stream_target("Game"),
+        ]))
+    });
+    add_device(&mut harness, 1);
+    let firefox = open_stream(&mut harness, "Firefox", state(0.8, false), None);
+    let game = open_stream(&mut harness, "Game", state(0.6, false), None);
+    let discord = open_stream(&mut harness, "Discord", state(1.0, false), None);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![AudioControl::MeterSubscribe {
+            stream_id: discord,
+            interval: Duration::from_millis(50),
+        }]
+    );
+
+    let peak = |harness: &mut Harness, peak| {
+        harness.audio(AudioEvent::StreamEvent {
+            stream_id: discord,
+            stream_event: StreamEvent::Peak(peak),
+        })
+    };
+    peak(&mut harness, 0.05);
+    assert_eq!(harness.take_audio_controls(), vec![]);
+    peak(&mut harness, 0.5);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![
+            control(firefox, StreamControl::SetVolume(0.4)),
+            control(game, StreamControl::SetVolume(0.3)),
+        ]
+    );
+
+    // The backend reporting the ducked volume doesn't count as a change by the user, but
+    // changing it in another application does.
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: firefox,
+        stream_event: StreamEvent::StateChanged(state(0.4, false)),
+    });
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: game,
+        stream_event: StreamEvent::StateChanged(state(0.9, false)),
+    });
+    peak(&mut harness, 0.0);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.8))]
+    );
+}
+
+#[test]
+fn ducking_attack_and_release_follow_the_clock() {
+    let mut harness = Harness::new(Config {
+        ducking: Some(Ducking {
+            priority: vec![StreamPattern {
+                name: Some("Discord".into()),
+                executable: None,
+            }],
+            amount: 0.5,
+            threshold: 0.1,
+            attack_ms: 100,
+            hold_ms: 200,
+            release_ms: 100,
+        }),
+        ..bound_config(channel_0(vec![stream_target("Firefox")]))
+    });
+    add_device(&mut harness, 1);
+    let firefox = open_stream(&mut harness, "Firefox", state(0.8, false), None);
+    let discord = open_stream(&mut harness, "Discord", state(1.0, false), None);
+    let peak = |harness: &mut Harness, peak| {
+        harness.audio(AudioEvent::StreamEvent {
+            stream_id: discord,
+            stream_event: StreamEvent::Peak(peak),
+        })
+    };
+    let step = Duration::from_millis(50);
+    harness.clear();
+
+    // The volume moves halfway to the ducked volume in half of the attack time, without any
+    // more peaks.
+    peak(&mut harness, 0.5);
+    assert_eq!(harness.take_audio_controls(), vec![]);
+    harness.advance(step);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.8 * 0.75))]
+    );
+    harness.advance(step);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.8 * 0.5))]
+    );
+
+    // It is held for a while after the last audible peak, and then released.
+    peak(&mut harness, 0.0);
+    for _ in 0..3 {
+        harness.advance(step);
+    }
+    assert_eq!(harness.take_audio_controls(), vec![]);
+    harness.advance(step);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.8 * 0.75))]
+    );
+    harness.advance(step);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.8))]
+    );
+    harness.advance(step);
+    assert_eq!(harness.take_audio_controls(), vec![]);
+}
+
+#[test]
+fn ducking_may_go_below_the_volume_limits() {
+    let mut harness = Harness::new(Config {
+        ducking: Some(Ducking {
+            priority: vec![StreamPattern {
+                name: Some("Discord".into()),
+                executable: None,
+            }],
+            amount: 0.5,
+            threshold: 0.1,
+            attack_ms: 0,
+            hold_ms: 0,
+            release_ms: 0,
+        }),
+        ..bound_config(channel_0(vec![Target {
+            limits: Some(VolumeLimits {
+                min: 0.5,
+                max: 1.0,
+                external: ExternalChange::Clamp,
+            }),
+            ..stream_target("Firefox")
+        }]))
+    });
+    add_device(&mut harness, 1);
+    let firefox = open_stream(&mut harness, "Firefox", state(0.8, false), None);
+    let discord = open_stream(&mut harness, "Discord", state(1.0, false), None);
+    harness.clear();
+
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: discord,
+        stream_event: StreamEvent::Peak(0.5),
+    });
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.4))]
+    );
+    // The backend reports the ducked volume, which is not clamped, so the stream stays ducked.
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: firefox,
+        stream_event: StreamEvent::StateChanged(state(0.4, false)),
+    });
+    assert_eq!(harness.take_audio_controls(), vec![]);
+
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: discord,
+        stream_event: StreamEvent::Peak(0.0),
+    });
+    harness.advance(Duration::from_millis(20));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.8))]
+    );
+
+    // Once the ducking has ended, the limits apply again.
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: firefox,
+        stream_event: StreamEvent::StateChanged(state(0.1, false)),
+    });
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.5))]
+    );
+}
+
+#[test]
+fn solo_mutes_other_channels() {
+    let mut config = bound_config(channel_0(vec![stream_target("Firefox")]));
+    for &(channel, name) in &[(1, "Game"), (2, "Music")] {
+        config.profiles[0].bindings.push(ChannelBindings {
+            targets: vec![stream_target(name)],
+            ..ChannelBindings::new(ChannelKey {
+                device: SERIAL.into(),
+                channel,
+            })
+        });
+    }
+    let mut harness = Harness::new(config);
+    open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    let game = open_stream(&mut harness, "Game", state(0.5, false), None);
+    let music = open_stream(&mut harness, "Music", state(0.5, true), None);
+    let device_id = add_device(&mut harness, 3);
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::ToggleSolo);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(game, StreamControl::SetMuted(true))]
+    );
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(device_id, 0, ChannelOutput::Soloed(true))]
+    );
+
+    // Streams that open during the solo are muted too, and restored with the others.
+    harness.audio(AudioEvent::StreamClosed { stream_id: game });
+    let game = open_stream(&mut harness, "Game", state(0.5, false), None);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(game, StreamControl::SetMuted(true))]
+    );
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::OpenMenu);
+    assert_eq!(
+        harness.take_control_outputs().pop(),
+        Some(output(device_id, 0, highlighted("None", 0, 7, 0)))
+    );
+    for _ in 0..6 {
+        input(&mut harness, device_id, 0, ChannelInput::MenuNext);
+    }
+    assert_eq!(
+        harness.take_control_outputs().pop(),
+        Some(output(device_id, 0, highlighted("End Solo", 6, 7, 0)))
+    );
+    input(&mut harness, device_id, 0, ChannelInput::MenuSelect);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![
+            control(music, StreamControl::SetMuted(true)),
+            control(game, StreamControl::SetMuted(false)),
+        ]
+    );
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![
+            output(device_id, 0, ChannelOutput::MenuClosed),
+            output(device_id, 0, ChannelOutput::Soloed(false)),
+        ]
+    );
+}
+
+#[test]
+fn gestures_run_configured_actions() {
+    let mut config = bound_config(channel_0(vec![stream_target("Firefox")]));
+    config.gestures = Gestures {
+        double_click: Action::ToggleSolo,
+        press_turn: Action::None,
+        ..Gestures::default()
+    };
+    let mut harness = Harness::new(config);
+    let firefox = open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    let device_id = add_device(&mut harness, 2);
+    harness.clear();
+
+    // Channel 1 is unbound, so soloing channel 0 has nothing to mute.
+    for &pressed in &[true, false, true, false] {
+        input(&mut harness, device_id, 0, ChannelInput::Button(pressed));
+    }
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(device_id, 0, ChannelOutput::Soloed(true))]
+    );
+    assert_eq!(harness.take_audio_controls(), vec![]);
+
+    input(&mut harness, device_id, 0, ChannelInput::Encoder(1));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(firefox, StreamControl::SetVolume(0.5 + 0.02))]
+    );
+    input(&mut harness, device_id, 0, ChannelInput::Button(true));
+    input(&mut harness, device_id, 0, ChannelInput::Encoder(1));
+    input(&mut harness, device_id, 0, ChannelInput::Button(false));
+    assert_eq!(harness.take_audio_controls(), vec![]);
+}
+
+#[test]
+fn gestures_run_other_actions() {
+    let pattern = |name: &str| StreamPattern {
+        name: Some(name.into()),
+        executable: None,
+    };
+    let mut config = bound_config(channel_0(vec![stream_target("Firefox")]));
+    config.gestures = Gestures {
+        click: Action::SetDefaultDevice(pattern("Headphones")),
+        turn: Action::StepStream(pattern("Music")),
+        press_turn: Action::Media(MediaKey::PlayPause),
+        ..Gestures::default()
+    };
+    let mut harness = Harness::new(config);
+    open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    let headphones = open_stream(&mut harness, "Headphones", state(0.5, false), None);
+    let music = open_stream(&mut harness, "Music", state(0.5, false), Some(headphones));
+    let device_id = add_device(&mut harness, 1);
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::Button(true));
+    input(&mut harness, device_id, 0, ChannelInput::Button(false));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![AudioControl::SetDefaultDevice {
+            stream_id: headphones
+        }]
+    );
+
+    input(&mut harness, device_id, 0, ChannelInput::Encoder(2));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(music, StreamControl::SetVolume(0.5 + 0.04))]
+    );
+
+    input(&mut harness, device_id, 0, ChannelInput::Button(true));
+    input(&mut harness, device_id, 0, ChannelInput::Encoder(1));
+    input(&mut harness, device_id, 0, ChannelInput::Button(false));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![AudioControl::Media(MediaKey::PlayPause)]
+    );
+}
+
+#[test]
+fn step_stream_uses_channel_settings() {
+    let mut config = bound_config(channel_0(vec![Target {
+        volume_step: 0.1,
+        limits: Some(VolumeLimits {
+            min: 0.0,
+            max: 0.65,
+            external: ExternalChange::Display,
+        }),
+        ..stream_target("Firefox")
+    }]));
+    config.gestures = Gestures {
+        turn: Action::StepStream(StreamPattern {
+            name: Some("Music".into()),
+            executable: None,
+        }),
+        ..Gestures::default()
+    };
+    let mut harness = Harness::new(config);
+    let music = open_stream(&mut harness, "Music", state(0.5, false), None);
+    let device_id = add_device(&mut harness, 1);
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::Encoder(1));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(music, StreamControl::SetVolume(0.6))]
+    );
+    input(&mut harness, device_id, 0, ChannelInput::Encoder(1));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(music, StreamControl::SetVolume(0.65))]
+    );
+}
+
+#[test]
+fn player_actions_control_channel_streams() {
+    let mut config = bound_config(channel_0(vec![stream_target("Music")]));
+    config.gestures = Gestures {
+        click: Action::Player(MediaKey::PlayPause),
+        turn: Action::Seek(5),
+        ..Gestures::default()
+    };
+    let mut harness = Harness::new(config);
+    let music = open_stream(&mut harness, "Music", state(0.5, false), None);
+    let device_id = add_device(&mut harness, 1);
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::Button(true));
+    input(&mut harness, device_id, 0, ChannelInput::Button(false));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![AudioControl::MediaControl {
+            stream_ids: vec![music],
+            media_control: MediaControl::Key(MediaKey::PlayPause),
+        }]
+    );
+
+    input(&mut harness, device_id, 0, ChannelInput::Encoder(-2));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![AudioControl::MediaControl {
+            stream_ids: vec![music],
+            media_control: MediaControl::Seek(-10_000),
+        }]
+    );
+}
+
+#[test]
+fn output_select_mode_sets_default_device() {
+    let mut harness = Harness::new(bound_config(ChannelBindings {
+        mode: ChannelMode::OutputSelect,
+        ..channel_0(vec![Target::new(TargetKind::DefaultDevice)])
+    }));
+    let speakers = open_stream(&mut harness, "Speakers", state(0.5, false), None);
+    let headset = open_stream(&mut harness, "Headset", state(0.5, false), None);
+    open_stream(&mut harness, "Firefox", state(0.5, false), Some(speakers));
+    harness.audio(AudioEvent::DefaultDeviceChanged {
+        stream_id: Some(speakers),
+    });
+    let device_id = add_device(&mut harness, 1);
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::Encoder(-1));
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![
+            output(device_id, 0, ChannelOutput::MenuOpened),
+            output(device_id, 0, highlighted("Speakers", 1, 2, 0)),
+            output(device_id, 0, highlighted("Headset", 0, 2, 0)),
+        ]
+    );
+    assert_eq!(harness.take_audio_controls(), vec![]);
+
+    input(&mut harness, device_id, 0, ChannelInput::Button(true));
+    input(&mut harness, device_id, 0, ChannelInput::Button(false));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![AudioControl::SetDefaultDevice { stream_id: headset }]
+    );
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(device_id, 0, ChannelOutput::MenuClosed)]
+    );
+}
+
+#[test]
+fn push_to_talk_unmutes_while_held() {
+    let mut harness = Harness::new(bound_config(ChannelBindings {
+        mode: ChannelMode::PushToTalk,
+        ..channel_0(vec![stream_target("Microphone")])
+    }));
+    let microphone = open_stream(&mut harness, "Microphone", state(0.5, true), None);
+    let device_id = add_device(&mut harness, 1);
+    harness.clear();
+
+    input(&mut harness, device_id, 0, ChannelInput::Button(true));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(microphone, StreamControl::SetMuted(false))]
+    );
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: microphone,
+        stream_event: StreamEvent::StateChanged(state(0.5, false)),
+    });
+    // Releasing restores the previous state instead of clicking.
+    input(&mut harness, device_id, 0, ChannelInput::Button(false));
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(microphone, StreamControl::SetMuted(true))]
+    );
+}
+
+#[test]
+fn switch_profile_command_restores_bindings() {
+    let mut config = bound_config(channel_0(vec![stream_target("Firefox")]));
+    config.profiles.push(Profile {
+        name: "Gaming".into(),
+        bindings: vec![channel_0(vec![stream_target("Game")])],
+    });
+    let mut harness = Harness::new(config);
+    open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    let game = open_stream(&mut harness, "Game", state(0.9, false), None);
+    let device_id = add_device(&mut harness, 1);
+    harness.clear();
+
+    harness.command(Command::SwitchProfile("Gaming".into()));
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(0.9, false))
+        )]
+    );
+    input(&mut harness, device_id, 0, ChannelInput::ToggleMuted);
+    assert_eq!(
+        harness.take_audio_controls(),
+        vec![control(game, StreamControl::ToggleMuted)]
+    );
+
+    // Unknown profiles are ignored.
+    harness.command(Command::SwitchProfile("Missing".into()));
+    assert_eq!(harness.take_control_outputs(), vec![]);
+}
+
+#[test]
+fn automatic_profile_switch_is_not_saved() {
+    let path = std::env::temp_dir().join(format!(
+        "windowmaster-auto-profile-{}.toml",
+        std::process::id()
+    ));
+    let mut config = bound_config(channel_0(vec![stream_target("Firefox")]));
+    config.profiles.push(Profile {
+        name: "Gaming".into(),
+        bindings: vec![channel_0(vec![stream_target("Game")])],
+    });
+    config.auto_profile = AutoProfile {
+        debounce_ms: 0,
+        fallback: None,
+        rules: vec![FocusRule {
+            profile: "Gaming".into(),
+            pattern: StreamPattern {
+                name: Some("Game".into()),
+                executable: None,
+            },
+        }],
+    };
+    config.save(&path).unwrap();
+
+    let mut harness = Harness::with_config_path(path.clone());
+    let game = open_stream(&mut harness, "Game", state(0.9, false), None);
+    let device_id = add_device(&mut harness, 1);
+    harness.clear();
+    harness.audio(AudioEvent::WindowFocusChanged {
+        stream_id: Some(game),
+        application: None,
+    });
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(0.9, false))
+        )]
+    );
+    assert_eq!(Config::load(&path).unwrap().active_profile, "Default");
+
+    harness.command(Command::SwitchProfile("Gaming".into()));
+    assert_eq!(Config::load(&path).unwrap().active_profile, "Gaming");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn focused_application_switches_profile_without_a_stream() {
+    let mut config = bound_config(channel_0(vec![stream_target("Firefox")]));
+    config.profiles.push(Profile {
+        name: "Gaming".into(),
+        bindings: vec![channel_0(vec![stream_target("Music")])],
+    });
+    config.auto_profile = AutoProfile {
+        debounce_ms: 100,
+        fallback: Some("Default".into()),
+        rules: vec![FocusRule {
+            profile: "Gaming".into(),
+            pattern: StreamPattern {
+                name: None,
+                executable: Some("game.exe".into()),
+            },
+        }],
+    };
+    let mut harness = Harness::new(config);
+    open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    open_stream(&mut harness, "Music", state(0.3, false), None);
+    let device_id = add_device(&mut harness, 1);
+    harness.clear();
+
+    let focus = |executable: &str| AudioEvent::WindowFocusChanged {
+        stream_id: None,
+        application: Some(Application {
+            executable: Some(executable.into()),
+            name: None,
+        }),
+    };
+    harness.audio(focus(r"C:\Games\Game.exe"));
+    assert_eq!(harness.take_control_outputs(), vec![]);
+    harness.advance(Duration::from_millis(100));
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(0.3, false))
+        )]
+    );
+
+    harness.audio(focus(r"C:\Windows\explorer.exe"));
+    harness.advance(Duration::from_millis(100));
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(0.5, false))
+        )]
+    );
+}
+
+#[test]
+fn removed_device_is_ignored() {
+    let mut harness = Harness::new(bound_config(channel_0(vec![stream_target("Firefox")])));
+    let firefox = open_stream(&mut harness, "Firefox", state(0.5, false), None);
+    let device_id = add_device(&mut harness, 1);
+    input(&mut harness, device_id, 0, ChannelInput::OpenMenu);
+    harness.control(ControlInput::DeviceRemoved(device_id));
+    harness.clear();
+
+    harness.audio(AudioEvent::StreamEvent {
+        stream_id: firefox,
+        stream_event: StreamEvent::StateChanged(state(0.7, false)),
+    });
+    input(&mut harness, device_id, 0, ChannelInput::StepVolume(1));
+    input(&mut harness, device_id, 0, ChannelInput::MenuSelect);
+    assert_eq!(harness.take_control_outputs(), vec![]);
+    assert_eq!(harness.take_audio_controls(), vec![]);
+
+    // The bindings are kept for when it comes back.
+    let device_id = add_device(&mut harness, 1);
+    assert_eq!(
+        harness.take_control_outputs(),
+        vec![output(
+            device_id,
+            0,
+            ChannelOutput::StateChanged(state(0.7, false))
+        )]
+    );
+}